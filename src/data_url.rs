@@ -0,0 +1,329 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD, write::EncoderWriter};
+use serde::{
+    Serialize,
+    ser::{Error as _, SerializeMap, SerializeSeq},
+};
+use serde_json::{
+    Value,
+    ser::{CompactFormatter, Formatter, PrettyFormatter, Serializer},
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Write},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::ENVELOPE_DATA_KEY;
+
+// --- Data URLs ---
+/// Builds the data URL of an image in a single allocation.
+pub(crate) fn data_url(mime_type: &str, bytes: &[u8]) -> String {
+    let prefix = data_url_prefix(mime_type);
+    let mut data_url =
+        String::with_capacity(prefix.len() + base64::encoded_len(bytes.len(), true).unwrap_or(0));
+    data_url.push_str(&prefix);
+    STANDARD.encode_string(bytes, &mut data_url);
+    data_url
+}
+
+fn data_url_prefix(mime_type: &str) -> String {
+    format!("data:{};base64,", mime_type)
+}
+
+/// Images whose data URLs are only produced when the document holding them is serialized.
+///
+/// Inlined fields hold a short placeholder until then, and the serializer streams the
+/// base64 encoding of the image straight into the output writer in its place. The encoded
+/// data URL (a third larger than the image) is therefore never held in memory, and neither
+/// is a serialized copy of the document.
+#[derive(Debug)]
+pub(crate) struct DataUrls {
+    /// Random part of the placeholders, so they cannot be mistaken for document content.
+    nonce: u64,
+    /// Number of images deferred so far, which numbers the placeholders.
+    deferred: AtomicUsize,
+    images: Mutex<HashMap<String, DeferredImage>>,
+}
+
+#[derive(Debug)]
+struct DeferredImage {
+    mime_type: String,
+    bytes: Vec<u8>,
+}
+
+impl Default for DataUrls {
+    fn default() -> Self {
+        DataUrls {
+            nonce: fastrand::u64(..),
+            deferred: AtomicUsize::new(0),
+            images: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl DataUrls {
+    /// Takes over an image and returns the placeholder to store in its field.
+    pub(crate) fn defer(&self, mime_type: String, bytes: Vec<u8>) -> String {
+        let number = self.deferred.fetch_add(1, Ordering::Relaxed);
+        let placeholder = format!("fcjp-data-url:{:016x}:{}", self.nonce, number);
+        let mut images = self.images.lock().unwrap_or_else(|e| e.into_inner());
+        images.insert(placeholder.clone(), DeferredImage { mime_type, bytes });
+        placeholder
+    }
+
+    /// Moves the images whose placeholders occur in `document` out into a set of their own,
+    /// so that they can be written, and then dropped, together with that document.
+    pub(crate) fn take(&self, document: &Value) -> DataUrls {
+        let mut images = self.images.lock().unwrap_or_else(|e| e.into_inner());
+        let mut taken = HashMap::new();
+        if !images.is_empty() {
+            take_placeholders(document, &mut images, &mut taken);
+        }
+        DataUrls {
+            nonce: self.nonce,
+            deferred: AtomicUsize::new(0),
+            images: Mutex::new(taken),
+        }
+    }
+
+    /// Replaces every placeholder in `document` with its data URL, for documents that are
+    /// handed back to the caller instead of being serialized.
+    pub(crate) fn resolve(&self, document: &mut Value) {
+        let images = self.images.lock().unwrap_or_else(|e| e.into_inner());
+        if !images.is_empty() {
+            resolve_placeholders(document, &images);
+        }
+    }
+
+    /// Serializes `document` as pretty-printed JSON, streaming in the deferred data URLs.
+    pub(crate) fn write_pretty(&self, writer: &mut dyn Write, document: &Value) -> io::Result<()> {
+        self.write_with(writer, document, PrettyFormatter::new())
+    }
+
+    /// Serializes `document` on a single line, streaming in the deferred data URLs.
+    pub(crate) fn write_compact(&self, writer: &mut dyn Write, document: &Value) -> io::Result<()> {
+        self.write_with(writer, document, CompactFormatter)
+    }
+
+    /// Serializes the crawl/batch `envelope` as pretty-printed JSON, with the pages of its
+    /// `data` array taken from `pages`, each with its own images, as they arrive. The images
+    /// of a page are dropped as soon as it is written.
+    pub(crate) fn write_envelope_pretty(
+        writer: &mut dyn Write,
+        envelope: &Value,
+        pages: impl Iterator<Item = io::Result<(Value, DataUrls)>>,
+    ) -> io::Result<()> {
+        let images = RefCell::new(HashMap::new());
+        let formatter = DataUrlFormatter {
+            inner: PrettyFormatter::new(),
+            images: &images,
+        };
+        let envelope = StreamedEnvelope {
+            envelope,
+            pages: RefCell::new(pages),
+            images: &images,
+        };
+        envelope.serialize(&mut Serializer::with_formatter(writer, formatter))?;
+        Ok(())
+    }
+
+    fn write_with<F: Formatter>(
+        &self,
+        writer: &mut dyn Write,
+        document: &Value,
+        formatter: F,
+    ) -> io::Result<()> {
+        let mut images = self.images.lock().unwrap_or_else(|e| e.into_inner());
+        // The images are lent to the formatter while the document is written
+        let lent = RefCell::new(std::mem::take(&mut *images));
+        let formatter = DataUrlFormatter {
+            inner: formatter,
+            images: &lent,
+        };
+        let written = document.serialize(&mut Serializer::with_formatter(writer, formatter));
+        *images = lent.into_inner();
+        Ok(written?)
+    }
+
+    fn into_images(self) -> HashMap<String, DeferredImage> {
+        self.images.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A crawl/batch envelope whose `data` array is filled from `pages` while it is serialized.
+struct StreamedEnvelope<'a, I> {
+    envelope: &'a Value,
+    pages: RefCell<I>,
+    /// The images of the page being written, shared with the [`DataUrlFormatter`].
+    images: &'a RefCell<HashMap<String, DeferredImage>>,
+}
+
+impl<I> Serialize for StreamedEnvelope<'_, I>
+where
+    I: Iterator<Item = io::Result<(Value, DataUrls)>>,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Value::Object(fields) = self.envelope else {
+            return self.envelope.serialize(serializer);
+        };
+        let mut map = serializer.serialize_map(Some(fields.len()))?;
+        for (key, value) in fields {
+            if key == ENVELOPE_DATA_KEY {
+                map.serialize_entry(key, &StreamedPages(self))?;
+            } else {
+                map.serialize_entry(key, value)?;
+            }
+        }
+        map.end()
+    }
+}
+
+struct StreamedPages<'a, 'b, I>(&'a StreamedEnvelope<'b, I>);
+
+impl<I> Serialize for StreamedPages<'_, '_, I>
+where
+    I: Iterator<Item = io::Result<(Value, DataUrls)>>,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let StreamedPages(envelope) = self;
+        let mut seq = serializer.serialize_seq(None)?;
+        loop {
+            let page = envelope.pages.borrow_mut().next();
+            let Some(page) = page else {
+                break;
+            };
+            let (page, page_urls) = page.map_err(S::Error::custom)?;
+            *envelope.images.borrow_mut() = page_urls.into_images();
+            let written = seq.serialize_element(&page);
+            envelope.images.borrow_mut().clear();
+            written?;
+        }
+        seq.end()
+    }
+}
+
+fn take_placeholders(
+    value: &Value,
+    images: &mut HashMap<String, DeferredImage>,
+    taken: &mut HashMap<String, DeferredImage>,
+) {
+    match value {
+        Value::String(string) => {
+            if let Some((placeholder, image)) = images.remove_entry(string.as_str()) {
+                taken.insert(placeholder, image);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                take_placeholders(item, images, taken);
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values() {
+                take_placeholders(item, images, taken);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn resolve_placeholders(value: &mut Value, images: &HashMap<String, DeferredImage>) {
+    match value {
+        Value::String(string) => {
+            if let Some(image) = images.get(string.as_str()) {
+                *string = data_url(&image.mime_type, &image.bytes);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                resolve_placeholders(item, images);
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                resolve_placeholders(item, images);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Formats JSON like `inner`, but writes a data URL wherever a string consists of a
+/// placeholder. Placeholders contain nothing that needs escaping, so each one reaches
+/// [`Formatter::write_string_fragment`] in one piece.
+struct DataUrlFormatter<'a, F> {
+    inner: F,
+    images: &'a RefCell<HashMap<String, DeferredImage>>,
+}
+
+impl<F: Formatter> Formatter for DataUrlFormatter<'_, F> {
+    fn write_string_fragment<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        fragment: &str,
+    ) -> io::Result<()> {
+        let images = self.images.borrow();
+        let Some(image) = images.get(fragment) else {
+            return self.inner.write_string_fragment(writer, fragment);
+        };
+        writer.write_all(data_url_prefix(&image.mime_type).as_bytes())?;
+        let mut encoder = EncoderWriter::new(writer, &STANDARD);
+        encoder.write_all(&image.bytes)?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    // The remaining methods only pass the layout of `inner` through
+
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.begin_array(writer)
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_array(writer)
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.inner.begin_array_value(writer, first)
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_array_value(writer)
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.begin_object(writer)
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_object(writer)
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.inner.begin_object_key(writer, first)
+    }
+
+    fn end_object_key<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_object_key(writer)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.begin_object_value(writer)
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_object_value(writer)
+    }
+}
@@ -0,0 +1,100 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::AppError;
+
+/// Glob used when no `--include` pattern is given.
+pub const DEFAULT_INCLUDE_GLOB: &str = "**/*.{json,jsonl}";
+
+// --- Input file selection ---
+/// Decides which files below the input root are picked up for processing.
+///
+/// Patterns are matched against the path relative to the input root, using `/`
+/// as the separator on every platform.
+#[derive(Debug, Clone)]
+pub struct InputFilter {
+    include: GlobSet,
+    exclude: GlobSet,
+    skip_dirs: Vec<PathBuf>,
+}
+
+impl InputFilter {
+    /// Builds a filter from include and exclude glob patterns.
+    /// An empty include list falls back to [`DEFAULT_INCLUDE_GLOB`].
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, AppError> {
+        let include = if include.is_empty() {
+            build_glob_set(&[DEFAULT_INCLUDE_GLOB.to_string()])?
+        } else {
+            build_glob_set(include)?
+        };
+        Ok(InputFilter {
+            include,
+            exclude: build_glob_set(exclude)?,
+            skip_dirs: Vec::new(),
+        })
+    }
+
+    /// Prunes a directory (and everything below it) from the walk.
+    /// Used to keep the output directories out of the input set when they live inside it.
+    pub fn skip_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.skip_dirs.push(dir.into());
+        self
+    }
+
+    /// Returns true if the given path, relative to the input root, should be processed.
+    pub fn matches(&self, relative_path: &Path) -> bool {
+        let normalized = normalize_separators(relative_path);
+        self.include.is_match(&normalized) && !self.exclude.is_match(&normalized)
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, AppError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(AppError::InvalidGlob)?;
+        builder.add(glob);
+    }
+    builder.build().map_err(AppError::InvalidGlob)
+}
+
+fn normalize_separators(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Recursively walks `root` and returns every file accepted by `filter`, sorted by path.
+pub fn discover_input_files(root: &Path, filter: &InputFilter) -> Result<Vec<PathBuf>, AppError> {
+    let mut files = Vec::new();
+    let walker = WalkDir::new(root).into_iter().filter_entry(|entry| {
+        !(entry.file_type().is_dir() && filter.skip_dirs.iter().any(|d| d == entry.path()))
+    });
+
+    for entry in walker {
+        let entry = entry.map_err(AppError::Walk)?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if filter.matches(relative) {
+            files.push(entry.into_path());
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Maps the parent directory of `file` (somewhere below `root`) onto `output_root`,
+/// so that the output tree mirrors the relative layout of the input tree.
+pub fn mirrored_output_dir(root: &Path, file: &Path, output_root: &Path) -> PathBuf {
+    match file
+        .parent()
+        .and_then(|parent| parent.strip_prefix(root).ok())
+    {
+        Some(relative_dir) => output_root.join(relative_dir),
+        None => output_root.to_path_buf(),
+    }
+}
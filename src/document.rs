@@ -0,0 +1,759 @@
+use futures_util::{
+    Stream, StreamExt,
+    future::join,
+    stream::{self, BoxStream},
+};
+use serde_json::Value;
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    future::Future,
+    io::{self, BufRead, Write},
+    iter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{runtime::Handle, sync::mpsc};
+
+use crate::{
+    AppError, ENVELOPE_DATA_KEY, FieldReport, FieldStatus, FileProcessResult, JSONL_EXTENSION,
+    data_url::DataUrls,
+    discovery::{InputFilter, discover_input_files, mirrored_output_dir},
+    events::{EventHandler, FileResultHandler, ProgressEvent},
+    runtime::run_blocking,
+    sink::{OutputKind, OutputSink},
+};
+
+/// Returns the page documents of a Firecrawl `/crawl` or `/batch/scrape` status response,
+/// i.e. a root object whose `data` field is an array of objects.
+pub fn crawl_envelope_pages_mut(json_data: &mut Value) -> Option<&mut Vec<Value>> {
+    match json_data.get_mut(ENVELOPE_DATA_KEY) {
+        Some(Value::Array(pages)) if !pages.is_empty() && pages.iter().all(Value::is_object) => {
+            Some(pages)
+        }
+        _ => None,
+    }
+}
+
+/// Returns true for files that hold one JSON document per line (`.jsonl`).
+pub fn is_jsonl_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == JSONL_EXTENSION)
+}
+
+/// Where and how the rewritten documents of one input file are written.
+pub(crate) struct FileJob<'a> {
+    pub json_path: &'a Path,
+    pub output_dir: &'a Path,
+    /// Receives the rewritten documents.
+    pub sink: Arc<dyn OutputSink>,
+    /// Images whose data URLs are streamed into the documents as they are written.
+    pub data_urls: Arc<DataUrls>,
+    pub explode_envelopes: bool,
+    /// Skip the file if its output is newer than the input.
+    pub incremental: bool,
+    /// Receives the progress of the file.
+    pub events: &'a EventHandler,
+    /// Start of the skip message, e.g. "No valid screenshot URL found".
+    pub skip_reason: &'a str,
+}
+
+/// Rewrites the fields of one document, e.g. by inlining or extracting its screenshots.
+pub(crate) trait DocumentRewriter: Sync {
+    /// Receives the document, the JSON Pointer locating it within the file (non-empty for
+    /// pages of a crawl/batch envelope) and its 1-based line for JSON Lines input.
+    async fn rewrite(
+        &self,
+        document: &mut Value,
+        pointer_prefix: &str,
+        line: Option<usize>,
+    ) -> Vec<FieldReport>;
+
+    /// Maximum number of documents (JSON Lines lines, or pages of an envelope) rewritten at
+    /// once. The images of a document are held until it is written.
+    fn concurrency(&self) -> usize;
+}
+
+/// Reads a `.json` or `.jsonl` file, hands every document in it to `rewriter` and writes the
+/// result to the job's output directory (through its sink) if at least one field was rewritten.
+/// The documents of a file (JSON Lines lines, or pages of an envelope) are rewritten a few
+/// at a time and written out as they finish; parsing and serialization run on the runtime's
+/// blocking pool.
+pub(crate) async fn rewrite_file<R: DocumentRewriter>(
+    job: &FileJob<'_>,
+    rewriter: &R,
+) -> FileProcessResult {
+    let file = job.json_path.to_path_buf();
+    (job.events)(&ProgressEvent::Started { file: file.clone() });
+    let result = rewrite_file_contents(job, rewriter).await;
+    match &result {
+        FileProcessResult::Success(_) => {}
+        FileProcessResult::Skipped(reason) | FileProcessResult::UpToDate(reason) => {
+            (job.events)(&ProgressEvent::Skipped {
+                file,
+                reason: reason.clone(),
+            });
+        }
+        FileProcessResult::Rejected(_, reports) => {
+            let rejection = reports.iter().find_map(|report| match &report.status {
+                FieldStatus::Rejected(error) => Some(error.to_string()),
+                _ => None,
+            });
+            (job.events)(&ProgressEvent::Failed {
+                file,
+                error: rejection.unwrap_or_default(),
+            });
+        }
+        FileProcessResult::Failed(_, error) => {
+            (job.events)(&ProgressEvent::Failed {
+                file,
+                error: error.to_string(),
+            });
+        }
+    }
+    result
+}
+
+/// The files of an input directory, rewritten several at a time.
+pub(crate) struct DirJob<'a> {
+    pub input_dir: &'a Path,
+    pub image_dir: &'a Path,
+    pub output_dir: &'a Path,
+    pub input_filter: &'a InputFilter,
+    /// Give every file an image directory mirroring its place in the input tree, instead
+    /// of saving all images directly to `image_dir`.
+    pub mirror_images: bool,
+    /// Maximum number of files rewritten at once.
+    pub concurrency: usize,
+    /// Receives the [`ProgressEvent::Found`] event of the walk.
+    pub events: &'a EventHandler,
+    /// Receives the result of every file as soon as it is ready.
+    pub on_file_result: &'a FileResultHandler,
+}
+
+/// Walks the job's input directory (on the runtime's blocking pool) and hands every matching
+/// file to `rewrite`, together with its image and output directories. These mirror the input
+/// layout; output directories inside the input directory are not walked. Results are
+/// returned sorted by path.
+pub(crate) async fn rewrite_dir<F, Fut>(
+    job: &DirJob<'_>,
+    rewrite: F,
+) -> Result<Vec<(PathBuf, FileProcessResult)>, AppError>
+where
+    F: Fn(PathBuf, PathBuf, PathBuf) -> Fut,
+    Fut: Future<Output = FileProcessResult>,
+{
+    let input_dir = job.input_dir.to_path_buf();
+    let image_dir = job.image_dir.to_path_buf();
+    let output_dir = job.output_dir.to_path_buf();
+    let input_filter = job.input_filter.clone();
+    let (input_dir, image_dir, output_dir, json_files) = run_blocking(move || {
+        let input_dir = fs::canonicalize(&input_dir).map_err(|source| AppError::Read {
+            path: input_dir.clone(),
+            source,
+        })?;
+        let image_dir = canonical_output_dir(&image_dir);
+        let output_dir = canonical_output_dir(&output_dir);
+        let input_filter = input_filter.skip_dir(&image_dir).skip_dir(&output_dir);
+        let json_files = discover_input_files(&input_dir, &input_filter)?;
+        Ok::<_, AppError>((input_dir, image_dir, output_dir, json_files))
+    })
+    .await?;
+    (job.events)(&ProgressEvent::Found {
+        dir: input_dir.clone(),
+        files: json_files.len(),
+    });
+
+    let mut results: Vec<(PathBuf, FileProcessResult)> = stream::iter(json_files)
+        .map(|json_path| {
+            let file_image_dir = if job.mirror_images {
+                mirrored_output_dir(&input_dir, &json_path, &image_dir)
+            } else {
+                image_dir.clone()
+            };
+            let file_output_dir = mirrored_output_dir(&input_dir, &json_path, &output_dir);
+            let rewritten = rewrite(json_path.clone(), file_image_dir, file_output_dir);
+            async move {
+                let result = rewritten.await;
+                (job.on_file_result)(&json_path, &result);
+                (json_path, result)
+            }
+        })
+        .buffer_unordered(job.concurrency.max(1))
+        .collect()
+        .await;
+    results.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(results)
+}
+
+/// Canonical form of an output directory, so that it compares equal to the paths produced
+/// by the walk. Directories that do not exist yet (or never will, for sinks that do not
+/// write to disk) cannot be walked and are used as given.
+fn canonical_output_dir(dir: &Path) -> PathBuf {
+    fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())
+}
+
+async fn rewrite_file_contents<R: DocumentRewriter>(
+    job: &FileJob<'_>,
+    rewriter: &R,
+) -> FileProcessResult {
+    let json_path = job.json_path;
+    let file_name_os_str = match json_path.file_name() {
+        Some(name) => name,
+        None => {
+            return FileProcessResult::Failed(
+                "UnknownFile".to_string(),
+                AppError::InvalidPath(format!(
+                    "Could not get file name from path: {:?}",
+                    json_path
+                )),
+            );
+        }
+    };
+    let log_file_name = file_name_os_str.to_string_lossy().to_string(); // For logging, even if not perfect UTF-8
+
+    if let Some(output_path) = newer_output(job, file_name_os_str) {
+        let up_to_date_msg = format!(
+            "{} is up to date ({:?} is newer)",
+            log_file_name, output_path
+        );
+        return FileProcessResult::UpToDate(up_to_date_msg);
+    }
+
+    if is_jsonl_path(json_path) {
+        return rewrite_jsonl_file(job, file_name_os_str, log_file_name, rewriter).await;
+    }
+
+    let input_path = json_path.to_path_buf();
+    let parsed = run_blocking(move || {
+        let content = read_input(&input_path)?;
+        serde_json::from_str::<Value>(&content).map_err(AppError::Parse)
+    })
+    .await;
+    let mut json_data = match parsed {
+        Ok(jd) => jd,
+        Err(e) => return FileProcessResult::Failed(log_file_name, e),
+    };
+
+    if let Some(pages) = crawl_envelope_pages_mut(&mut json_data).map(std::mem::take) {
+        return rewrite_envelope_file(
+            job,
+            file_name_os_str,
+            log_file_name,
+            json_data,
+            pages,
+            rewriter,
+        )
+        .await;
+    }
+
+    let reports = rewrite_value(&mut json_data, None, rewriter).await;
+    let reports = match reports_to_write(job, &log_file_name, reports) {
+        Ok(reports) => reports,
+        Err(result) => return result,
+    };
+
+    // Use the original OsStr for the output JSON filename to handle non-UTF8 filenames correctly
+    let json_output_path = job.output_dir.join(file_name_os_str);
+    if let Err(e) = write_json(job, json_output_path, json_data).await {
+        return FileProcessResult::Failed(log_file_name, e);
+    }
+
+    FileProcessResult::Success(reports)
+}
+
+/// Rewrites one parsed document, or every page of it if it is a crawl/batch envelope.
+pub(crate) async fn rewrite_value<R: DocumentRewriter>(
+    json_data: &mut Value,
+    line: Option<usize>,
+    rewriter: &R,
+) -> Vec<FieldReport> {
+    // A crawl/batch status response holds many pages; each one is processed like a standalone document.
+    if !json_data.is_object() {
+        return vec![FieldReport {
+            line,
+            pointer: String::new(),
+            attempts: 0,
+            status: FieldStatus::Failed(AppError::NotObject),
+        }];
+    }
+    match crawl_envelope_pages_mut(json_data) {
+        Some(pages) => {
+            let prefixes: Vec<String> = (0..pages.len())
+                .map(|index| format!("/{}/{}", ENVELOPE_DATA_KEY, index))
+                .collect();
+            let rewrites: Vec<_> = pages
+                .iter_mut()
+                .zip(&prefixes)
+                .map(|(page, prefix)| rewriter.rewrite(page, prefix, line))
+                .collect();
+            stream::iter(rewrites)
+                .buffered(rewriter.concurrency().max(1))
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .flatten()
+                .collect()
+        }
+        None => rewriter.rewrite(json_data, "", line).await,
+    }
+}
+
+/// Passes the reports of a file through if its output should be written. Otherwise returns
+/// the [`unwritten_result`] for the file.
+fn reports_to_write(
+    job: &FileJob,
+    log_file_name: &str,
+    reports: Vec<FieldReport>,
+) -> Result<Vec<FieldReport>, FileProcessResult> {
+    if any_rewritten(&reports) {
+        return Ok(reports);
+    }
+    Err(unwritten_result(job, log_file_name, reports))
+}
+
+/// The result for a file none of whose fields was rewritten: skipped if nothing was found,
+/// rejected if any image was, up to date if everything was inlined already, or failed with
+/// the first error.
+fn unwritten_result(
+    job: &FileJob,
+    log_file_name: &str,
+    reports: Vec<FieldReport>,
+) -> FileProcessResult {
+    if reports.is_empty() {
+        let skip_msg = format!("{} in {}", job.skip_reason, log_file_name);
+        return FileProcessResult::Skipped(skip_msg);
+    }
+    if reports
+        .iter()
+        .any(|r| matches!(r.status, FieldStatus::Rejected(_)))
+    {
+        return FileProcessResult::Rejected(log_file_name.to_string(), reports);
+    }
+
+    // Nothing changed, so there is nothing worth writing out.
+    let first_error = reports.into_iter().find_map(|r| match (r.status, r.line) {
+        (FieldStatus::Failed(e), Some(line)) => Some(AppError::Line {
+            line,
+            source: Box::new(e),
+        }),
+        (FieldStatus::Failed(e), None) => Some(e),
+        _ => None,
+    });
+    match first_error {
+        Some(error) => FileProcessResult::Failed(log_file_name.to_string(), error),
+        None => {
+            let up_to_date_msg =
+                format!("All screenshots in {} are already inlined", log_file_name);
+            FileProcessResult::UpToDate(up_to_date_msg)
+        }
+    }
+}
+
+fn any_rewritten(reports: &[FieldReport]) -> bool {
+    reports.iter().any(|r| {
+        matches!(
+            r.status,
+            FieldStatus::Inlined { .. }
+                | FieldStatus::Linked { .. }
+                | FieldStatus::Extracted { .. }
+        )
+    })
+}
+
+/// In incremental mode, returns the existing output of `job` if it was modified after the
+/// input file. For an exploded envelope the first page file stands in for the whole output.
+fn newer_output(job: &FileJob, file_name_os_str: &OsStr) -> Option<PathBuf> {
+    if !job.incremental {
+        return None;
+    }
+    let input_modified = fs::metadata(job.json_path)
+        .and_then(|m| m.modified())
+        .ok()?;
+
+    let mut candidates = vec![job.output_dir.join(file_name_os_str)];
+    if job.explode_envelopes {
+        candidates.push(
+            job.output_dir
+                .join(exploded_page_file_name(job.json_path, 0)),
+        );
+    }
+    candidates.into_iter().find(|path| {
+        job.sink
+            .modified(path)
+            .is_some_and(|output_modified| output_modified > input_modified)
+    })
+}
+
+/// Processes the pages of a crawl/batch envelope, whose `data` array has been taken out of
+/// `envelope`. Pages are rewritten a few at a time and written out in order as soon as they
+/// are done, into the envelope or, when exploding envelopes, into files of their own, so
+/// only the images of those few pages are held at once.
+async fn rewrite_envelope_file<R: DocumentRewriter>(
+    job: &FileJob<'_>,
+    file_name_os_str: &OsStr,
+    log_file_name: String,
+    envelope: Value,
+    pages: Vec<Value>,
+    rewriter: &R,
+) -> FileProcessResult {
+    let data_urls = &job.data_urls;
+    let mut rewritten = stream::iter(pages.into_iter().enumerate())
+        .map(|(index, mut page)| async move {
+            let prefix = format!("/{}/{}", ENVELOPE_DATA_KEY, index);
+            let reports = rewriter.rewrite(&mut page, &prefix, None).await;
+            let images = data_urls.take(&page);
+            Ok(((page, images), reports))
+        })
+        .buffered(rewriter.concurrency().max(1));
+
+    let (mut held, mut reports) = match until_rewritten(&mut rewritten).await {
+        Ok(first) => first,
+        Err(e) => return FileProcessResult::Failed(log_file_name, e),
+    };
+    if !any_rewritten(&reports) {
+        return unwritten_result(job, &log_file_name, reports);
+    }
+
+    let (sender, mut receiver) = mpsc::channel(1);
+    let (forwarded, saved) = if job.explode_envelopes {
+        let write_pages = async move {
+            let mut index = 0;
+            for page in held {
+                write_page(job, index, page).await?;
+                index += 1;
+            }
+            while let Some(Ok(page)) = receiver.recv().await {
+                write_page(job, index, page).await?;
+                index += 1;
+            }
+            Ok(())
+        };
+        join(forward(rewritten, sender, reports), write_pages).await
+    } else {
+        if let Err(e) = rewrite_rest_without_runtime(&mut rewritten, &mut held, &mut reports).await
+        {
+            return FileProcessResult::Failed(log_file_name, e);
+        }
+        let json_output_path = job.output_dir.join(file_name_os_str);
+        let write_envelope = move |writer: &mut dyn Write| {
+            let pages = held
+                .drain(..)
+                .map(Ok)
+                .chain(iter::from_fn(|| receiver.blocking_recv()));
+            DataUrls::write_envelope_pretty(writer, &envelope, pages)
+        };
+        join(
+            forward(rewritten, sender, reports),
+            save_output(job, json_output_path, write_envelope),
+        )
+        .await
+    };
+    streamed_result(log_file_name, forwarded, saved)
+}
+
+/// Writes page `index` of an exploded envelope to a file of its own.
+async fn write_page(
+    job: &FileJob<'_>,
+    index: usize,
+    (page, images): (Value, DataUrls),
+) -> Result<(), AppError> {
+    let page_output_path = job
+        .output_dir
+        .join(exploded_page_file_name(job.json_path, index));
+    save_output(job, page_output_path, move |writer: &mut dyn Write| {
+        images.write_pretty(writer, &page)
+    })
+    .await
+}
+
+/// Processes a JSON Lines file: every non-blank line is an independent document.
+/// Lines are written back in their original order; a line that cannot be parsed is
+/// reported as a failure and copied through unchanged.
+async fn rewrite_jsonl_file<R: DocumentRewriter>(
+    job: &FileJob<'_>,
+    file_name_os_str: &OsStr,
+    log_file_name: String,
+    rewriter: &R,
+) -> FileProcessResult {
+    // Lines are read and rewritten a few at a time and written out in order as soon as they
+    // are done, so only those few lines and their images are held at once
+    let data_urls = &job.data_urls;
+    let concurrency = rewriter.concurrency().max(1);
+    let mut rewritten = read_lines(job.json_path, concurrency)
+        .enumerate()
+        .map(|(index, read)| async move {
+            let line = index + 1;
+            let (raw_line, parsed) = read?;
+            Ok(match parsed {
+                None => (OutputLine::Raw(raw_line), Vec::new()),
+                Some(Err(e)) => {
+                    let report = line_failure(line, AppError::Parse(e));
+                    (OutputLine::Raw(raw_line), vec![report])
+                }
+                Some(Ok(mut json_data)) => {
+                    let reports = rewrite_value(&mut json_data, Some(line), rewriter).await;
+                    if any_rewritten(&reports) {
+                        let images = data_urls.take(&json_data);
+                        (OutputLine::Rewritten(json_data, images), reports)
+                    } else {
+                        (OutputLine::Raw(raw_line), reports)
+                    }
+                }
+            })
+        })
+        .buffered(concurrency);
+
+    let (mut held, mut reports) = match until_rewritten(&mut rewritten).await {
+        Ok(first) => first,
+        Err(e) => return FileProcessResult::Failed(log_file_name, e),
+    };
+    if !any_rewritten(&reports) {
+        return unwritten_result(job, &log_file_name, reports);
+    }
+    if let Err(e) = rewrite_rest_without_runtime(&mut rewritten, &mut held, &mut reports).await {
+        return FileProcessResult::Failed(log_file_name, e);
+    }
+
+    let (sender, mut receiver) = mpsc::channel(1);
+    let jsonl_output_path = job.output_dir.join(file_name_os_str);
+    let write_lines = move |writer: &mut dyn Write| {
+        for output_line in held.drain(..) {
+            write_line(writer, output_line)?;
+        }
+        while let Some(output_line) = receiver.blocking_recv() {
+            write_line(writer, output_line?)?;
+        }
+        Ok(())
+    };
+    let (forwarded, saved) = join(
+        forward(rewritten, sender, reports),
+        save_output(job, jsonl_output_path, write_lines),
+    )
+    .await;
+    streamed_result(log_file_name, forwarded, saved)
+}
+
+/// A line of JSON Lines input, and the document parsed from it unless it is blank.
+type ReadLine = (String, Option<serde_json::Result<Value>>);
+
+/// The lines of a JSON Lines file, each parsed unless it is blank. They are read on the
+/// runtime's blocking pool as they are needed, at most `buffer` ahead.
+fn read_lines(json_path: &Path, buffer: usize) -> BoxStream<'static, Result<ReadLine, AppError>> {
+    let json_path = json_path.to_path_buf();
+    let parse = |raw_line: String| {
+        let parsed = (!raw_line.trim().is_empty()).then(|| serde_json::from_str(&raw_line));
+        (raw_line, parsed)
+    };
+    let Ok(handle) = Handle::try_current() else {
+        // Without a runtime there is nothing to read alongside, so the file is read at once
+        let lines = match read_input(&json_path) {
+            Ok(content) => content
+                .lines()
+                .map(|line| Ok(parse(line.to_string())))
+                .collect(),
+            Err(e) => vec![Err(e)],
+        };
+        return stream::iter(lines).boxed();
+    };
+
+    let (sender, receiver) = mpsc::channel(buffer);
+    handle.spawn_blocking(move || {
+        let read_error = |source| AppError::Read {
+            path: json_path.clone(),
+            source,
+        };
+        let lines = match fs::File::open(&json_path) {
+            Ok(file) => io::BufReader::new(file).lines(),
+            Err(e) => {
+                let _ = sender.blocking_send(Err(read_error(e)));
+                return;
+            }
+        };
+        for raw_line in lines {
+            let line = raw_line.map(parse).map_err(read_error);
+            let failed = line.is_err();
+            // The file is abandoned if a line cannot be read, or nobody waits for the rest
+            if sender.blocking_send(line).is_err() || failed {
+                return;
+            }
+        }
+    });
+    stream::unfold(receiver, |mut receiver| async move {
+        let line = receiver.recv().await?;
+        Some((line, receiver))
+    })
+    .boxed()
+}
+
+/// A document of a file as it comes out of the rewriter, with the reports of its fields,
+/// or the error that stopped the file from being read.
+type Rewritten<T> = Result<(T, Vec<FieldReport>), AppError>;
+
+/// Rewrites documents up to and including the first one with a rewritten field, and
+/// returns them with the reports so far. Nothing is written before that, as a file whose
+/// fields are all left untouched is not written at all.
+async fn until_rewritten<T, S>(documents: &mut S) -> Result<(Vec<T>, Vec<FieldReport>), AppError>
+where
+    S: Stream<Item = Rewritten<T>> + Unpin,
+{
+    let mut held = Vec::new();
+    let mut reports = Vec::new();
+    while let Some(document) = documents.next().await {
+        let (document, document_reports) = document?;
+        let rewritten = any_rewritten(&document_reports);
+        reports.extend(document_reports);
+        held.push(document);
+        if rewritten {
+            break;
+        }
+    }
+    Ok((held, reports))
+}
+
+/// Without a runtime the output is written inline rather than on the blocking pool, so it
+/// cannot wait for documents still being rewritten; they are all rewritten first.
+async fn rewrite_rest_without_runtime<T, S>(
+    documents: &mut S,
+    held: &mut Vec<T>,
+    reports: &mut Vec<FieldReport>,
+) -> Result<(), AppError>
+where
+    S: Stream<Item = Rewritten<T>> + Unpin,
+{
+    if Handle::try_current().is_ok() {
+        return Ok(());
+    }
+    while let Some(document) = documents.next().await {
+        let (document, document_reports) = document?;
+        reports.extend(document_reports);
+        held.push(document);
+    }
+    Ok(())
+}
+
+/// Sends the remaining documents to the output as they are rewritten and returns the
+/// reports of the whole file. It is polled before the output, so that an inline writer
+/// finds the channel closed. A file that cannot be read to the end is passed on as an
+/// error, so that its output fails instead of being cut short.
+async fn forward<T, S>(
+    mut documents: S,
+    sender: mpsc::Sender<io::Result<T>>,
+    mut reports: Vec<FieldReport>,
+) -> Result<Vec<FieldReport>, AppError>
+where
+    S: Stream<Item = Rewritten<T>> + Unpin,
+{
+    while let Some(document) = documents.next().await {
+        let (document, document_reports) = match document {
+            Ok(document) => document,
+            Err(e) => {
+                let _ = sender.send(Err(io::Error::other(e.to_string()))).await;
+                return Err(e);
+            }
+        };
+        reports.extend(document_reports);
+        // The output stops receiving if it cannot be written
+        if sender.send(Ok(document)).await.is_err() {
+            break;
+        }
+    }
+    Ok(reports)
+}
+
+/// The result of a file whose documents were forwarded to its output as they were
+/// rewritten. An input that could not be read is what failed the output, if both failed.
+fn streamed_result(
+    log_file_name: String,
+    forwarded: Result<Vec<FieldReport>, AppError>,
+    saved: Result<(), AppError>,
+) -> FileProcessResult {
+    match (forwarded, saved) {
+        (Ok(reports), Ok(())) => FileProcessResult::Success(reports),
+        (Err(e), _) | (Ok(_), Err(e)) => FileProcessResult::Failed(log_file_name, e),
+    }
+}
+
+/// A line of JSON Lines output.
+enum OutputLine {
+    /// Copied through as it was read.
+    Raw(String),
+    /// A rewritten document, with the images whose data URLs are streamed into it.
+    Rewritten(Value, DataUrls),
+}
+
+/// Writes one line of JSON Lines output and flushes it, dropping its images.
+fn write_line(writer: &mut dyn Write, output_line: OutputLine) -> io::Result<()> {
+    match output_line {
+        OutputLine::Raw(raw_line) => writer.write_all(raw_line.as_bytes())?,
+        OutputLine::Rewritten(json_data, data_urls) => {
+            data_urls.write_compact(writer, &json_data)?
+        }
+    }
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+/// Report for a JSON Lines line that failed as a whole.
+fn line_failure(line: usize, error: AppError) -> FieldReport {
+    FieldReport {
+        line: Some(line),
+        pointer: String::new(),
+        attempts: 0,
+        status: FieldStatus::Failed(error),
+    }
+}
+
+/// `crawl.json` page 3 becomes `crawl.page-3.json`. The `.page-` infix keeps page files
+/// apart from input files such as `crawl-3.json`, whose output goes to the same directory.
+fn exploded_page_file_name(json_path: &Path, index: usize) -> OsString {
+    let mut name = json_path
+        .file_stem()
+        .map(OsStr::to_os_string)
+        .unwrap_or_default();
+    name.push(format!(".page-{}", index));
+    if let Some(ext) = json_path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    name
+}
+
+fn read_input(json_path: &Path) -> Result<String, AppError> {
+    fs::read_to_string(json_path).map_err(|source| AppError::Read {
+        path: json_path.to_path_buf(),
+        source,
+    })
+}
+
+/// Writes a rewritten document as pretty-printed JSON.
+async fn write_json(
+    job: &FileJob<'_>,
+    output_path: PathBuf,
+    json_data: Value,
+) -> Result<(), AppError> {
+    let data_urls = Arc::clone(&job.data_urls);
+    save_output(job, output_path, move |writer: &mut dyn Write| {
+        data_urls.write_pretty(writer, &json_data)
+    })
+    .await
+}
+
+/// Streams a rewritten document into the job's sink and reports it.
+async fn save_output<F>(
+    job: &FileJob<'_>,
+    output_path: PathBuf,
+    mut write_output: F,
+) -> Result<(), AppError>
+where
+    F: FnMut(&mut dyn Write) -> std::io::Result<()> + Send + 'static,
+{
+    let sink = Arc::clone(&job.sink);
+    let path = output_path.clone();
+    run_blocking(move || sink.write_stream(OutputKind::Json, &path, &mut write_output)).await?;
+    (job.events)(&ProgressEvent::Saved {
+        file: job.json_path.to_path_buf(),
+        kind: OutputKind::Json,
+        path: output_path,
+    });
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use reqwest::StatusCode;
+use std::{error::Error, fmt, io, path::PathBuf, time::Duration};
+
+// --- Custom Error Type ---
+/// Everything that can go wrong while processing files. Variants keep the underlying
+/// error as their [`Error::source`], so callers can match on the kind of failure instead
+/// of inspecting messages.
+#[derive(Debug)]
+pub enum AppError {
+    /// An input file could not be read.
+    Read { path: PathBuf, source: io::Error },
+    /// An input document is not valid JSON.
+    Parse(serde_json::Error),
+    /// A document that should hold screenshot fields is not a JSON object.
+    NotObject,
+    /// The server answered with an error status.
+    Http {
+        url: String,
+        status: StatusCode,
+        /// Delay requested by the server through a `Retry-After` header.
+        retry_after: Option<Duration>,
+        source: reqwest::Error,
+    },
+    /// The request failed without a usable response: connection errors, timeouts,
+    /// or a body that could not be read.
+    Network { url: String, source: reqwest::Error },
+    /// The default HTTP client could not be created.
+    HttpClient(reqwest::Error),
+    /// A fetcher cannot handle this URL, e.g. a non-`file://` URL given to a
+    /// [`crate::FileFetcher`].
+    UnsupportedUrl { url: String, reason: &'static str },
+    /// The server answered successfully but sent no data.
+    EmptyBody { url: String },
+    /// The payload is not an acceptable image, e.g. the HTML or XML error page of an expired
+    /// URL; see [`crate::ImageValidation`].
+    InvalidImage { url: String, reason: String },
+    /// An output file or directory could not be written.
+    Write { path: PathBuf, source: io::Error },
+    /// A rewritten document could not be serialized.
+    Serialize(serde_json::Error),
+    /// A `data:` URL is malformed or not base64 encoded.
+    InvalidDataUrl {
+        reason: String,
+        source: Option<base64::DecodeError>,
+    },
+    /// An image got the name of a different image saved earlier in the same run, and the
+    /// processor is set to [`crate::OnCollision::Error`].
+    NameCollision {
+        path: PathBuf,
+        url: String,
+        existing_url: String,
+    },
+    /// The URL extension, `Content-Type` and content of an image name different types, and
+    /// the processor is set to [`crate::OnTypeMismatch::Fail`].
+    TypeMismatch {
+        url: String,
+        url_type: Option<String>,
+        content_type: Option<String>,
+        sniffed_type: Option<String>,
+    },
+    /// An image could not be transcoded; see [`crate::Transcode`].
+    Transcode { url: String, reason: String },
+    /// No usable file name or relative path could be derived.
+    InvalidPath(String),
+    /// A `--field` value is not a valid JSON Pointer.
+    InvalidPointer {
+        pointer: String,
+        reason: &'static str,
+    },
+    /// An `--include`/`--exclude` pattern is not a valid glob.
+    InvalidGlob(globset::Error),
+    /// The input directory could not be walked.
+    Walk(walkdir::Error),
+    /// The command line points at something that cannot be processed.
+    InvalidInput(String),
+    /// A failure in one line of a JSON Lines file.
+    Line { line: usize, source: Box<AppError> },
+    /// A blocking method was called from within an async runtime; use its `*_async`
+    /// version there.
+    AsyncContext,
+}
+
+impl AppError {
+    /// Returns true for failures that may go away on their own, such as timeouts,
+    /// rate limiting (HTTP 429) or an overloaded server (HTTP 503). These are retried.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AppError::Http { status, .. } => {
+                matches!(status.as_u16(), 408 | 425 | 429 | 500 | 502 | 503 | 504)
+            }
+            AppError::Network { source, .. } => {
+                source.is_timeout() || source.is_connect() || source.is_body()
+            }
+            AppError::Line { source, .. } => source.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// Delay the server asked for before the next attempt, if any.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AppError::Http { retry_after, .. } => *retry_after,
+            AppError::Line { source, .. } => source.retry_after(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::Read { path, source } => {
+                write!(f, "Failed to read file content from {:?}: {}", path, source)
+            }
+            AppError::Parse(source) => write!(f, "Failed to parse JSON: {}", source),
+            AppError::NotObject => write!(f, "Document is not a JSON object"),
+            AppError::Http { url, source, .. } => {
+                write!(f, "HTTP error downloading {}: {}", url, source)
+            }
+            AppError::Network { url, source } => {
+                write!(f, "HTTP request failed for {}: {}", url, source)
+            }
+            AppError::HttpClient(source) => write!(f, "Failed to create HTTP client: {}", source),
+            AppError::UnsupportedUrl { url, reason } => {
+                write!(f, "Cannot fetch {}: {}", url, reason)
+            }
+            AppError::EmptyBody { url } => write!(f, "Downloaded image from {} is empty", url),
+            AppError::InvalidImage { url, reason } => {
+                write!(
+                    f,
+                    "Downloaded payload from {} is not a valid image: {}",
+                    url, reason
+                )
+            }
+            AppError::Write { path, source } => write!(f, "Failed to save {:?}: {}", path, source),
+            AppError::Serialize(source) => write!(f, "Failed to serialize new JSON: {}", source),
+            AppError::InvalidDataUrl {
+                reason,
+                source: Some(source),
+            } => write!(f, "{}: {}", reason, source),
+            AppError::InvalidDataUrl { reason, .. } => write!(f, "{}", reason),
+            AppError::NameCollision {
+                path,
+                url,
+                existing_url,
+            } => write!(
+                f,
+                "Cannot save image from {} as {:?}: already taken by the image from {}",
+                url, path, existing_url
+            ),
+            AppError::TypeMismatch {
+                url,
+                url_type,
+                content_type,
+                sniffed_type,
+            } => write!(
+                f,
+                "Conflicting types for {}: URL extension says {}, Content-Type says {}, content is {}",
+                url,
+                url_type.as_deref().unwrap_or("unknown"),
+                content_type.as_deref().unwrap_or("unknown"),
+                sniffed_type.as_deref().unwrap_or("unknown")
+            ),
+            AppError::Transcode { url, reason } => {
+                write!(f, "Failed to transcode image from {}: {}", url, reason)
+            }
+            AppError::InvalidPath(reason) => write!(f, "{}", reason),
+            AppError::InvalidPointer { pointer, reason } => {
+                write!(f, "Invalid JSON Pointer '{}': {}", pointer, reason)
+            }
+            AppError::InvalidGlob(source) => write!(f, "Invalid glob pattern: {}", source),
+            AppError::Walk(source) => write!(f, "Failed to walk input directory: {}", source),
+            AppError::InvalidInput(reason) => write!(f, "{}", reason),
+            AppError::Line { line, source } => write!(f, "Line {}: {}", line, source),
+            AppError::AsyncContext => write!(
+                f,
+                "Blocking API called from within an async runtime; use the *_async methods"
+            ),
+        }
+    }
+}
+
+impl Error for AppError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AppError::Read { source, .. } | AppError::Write { source, .. } => Some(source),
+            AppError::Parse(source) | AppError::Serialize(source) => Some(source),
+            AppError::Http { source, .. }
+            | AppError::Network { source, .. }
+            | AppError::HttpClient(source) => Some(source),
+            AppError::InvalidDataUrl {
+                source: Some(source),
+                ..
+            } => Some(source),
+            AppError::InvalidGlob(source) => Some(source),
+            AppError::Walk(source) => Some(source),
+            AppError::Line { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+use crate::{FileProcessResult, sink::OutputKind};
+
+// --- Progress events ---
+/// What the library reports while it works. The library never prints; handlers decide how
+/// (and whether) to render events. Every event names the input `file` it belongs to, since
+/// events of different files interleave when files are processed in parallel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    /// The walk of an input directory found `files` input files, none of which has been
+    /// processed yet.
+    Found { dir: PathBuf, files: usize },
+    /// Processing of an input file started.
+    Started { file: PathBuf },
+    /// A screenshot fetch attempt is about to start; `attempt` counts from 1.
+    Downloading {
+        file: PathBuf,
+        pointer: String,
+        url: String,
+        attempt: u32,
+    },
+    /// A screenshot was fetched.
+    Downloaded {
+        file: PathBuf,
+        pointer: String,
+        bytes: usize,
+    },
+    /// An image or rewritten JSON file was written to the output sink.
+    Saved {
+        file: PathBuf,
+        kind: OutputKind,
+        path: PathBuf,
+    },
+    /// An image was identical to one stored already, so it was not written again; see
+    /// [`crate::ProcessorConfig::content_addressed`].
+    Deduplicated {
+        file: PathBuf,
+        path: PathBuf,
+        bytes: usize,
+    },
+    /// An image was saved under a different name than it was given, because the name was
+    /// taken by another image; see [`crate::OnCollision::Suffix`].
+    Renamed {
+        file: PathBuf,
+        requested: PathBuf,
+        path: PathBuf,
+    },
+    /// Nothing was written for the file: no screenshot field was found, or everything is
+    /// up to date already.
+    Skipped { file: PathBuf, reason: String },
+    /// The file could not be processed.
+    Failed { file: PathBuf, error: String },
+    /// One field of the file was left untouched; the rest of the file is still processed.
+    Warning {
+        file: PathBuf,
+        /// 1-based line number for documents read from a JSON Lines file.
+        line: Option<usize>,
+        pointer: String,
+        message: String,
+    },
+}
+
+/// Receives [`ProgressEvent`]s, possibly from several worker threads at once. To consume
+/// events elsewhere, forward them from a closure, e.g. into an `mpsc::Sender`.
+pub type EventHandler = dyn Fn(&ProgressEvent) + Send + Sync;
+
+/// An [`EventHandler`] that drops every event.
+pub fn ignore_events(_event: &ProgressEvent) {}
+
+/// Receives the result of every file of a directory as soon as it is processed, possibly
+/// from several worker threads at once; see [`crate::ProcessorConfig::on_file_result`].
+pub type FileResultHandler = dyn Fn(&Path, &FileProcessResult) + Send + Sync;
+
+/// A [`FileResultHandler`] that drops every result.
+pub fn ignore_file_results(_file: &Path, _result: &FileProcessResult) {}
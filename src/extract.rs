@@ -0,0 +1,430 @@
+use base64::{Engine as _, engine::general_purpose};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    AppError, FieldReport, FieldStatus, FileProcessResult, InputFilter,
+    default_screenshot_pointers, derived_image_filename,
+    document::{DirJob, DocumentRewriter, FileJob, rewrite_dir, rewrite_file},
+    events::{EventHandler, FileResultHandler, ProgressEvent, ignore_file_results},
+    runtime::{self, run_blocking},
+    sink::{FsSink, OutputKind, OutputSink},
+};
+
+/// Placeholder in a URL template that is replaced with the image file name.
+pub const FILENAME_PLACEHOLDER: &str = "{filename}";
+/// Placeholder in a URL template that is replaced with the hex SHA-256 of the image bytes.
+pub const SHA256_PLACEHOLDER: &str = "{sha256}";
+/// Placeholder in a URL template that is replaced with the image file extension, e.g. `png`.
+pub const EXTENSION_PLACEHOLDER: &str = "{ext}";
+/// Number of files [`extract_dir`] extracts at once unless configured otherwise.
+pub const DEFAULT_EXTRACT_CONCURRENCY: usize = 4;
+
+// --- Extraction options ---
+/// What an extracted field is rewritten to once its image has been written out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractTarget {
+    /// The image path relative to the directory of the output JSON file, using `/` separators.
+    RelativePath,
+    /// A URL built from a template such as `https://cdn.example/shots/{filename}` or
+    /// `https://cdn.example/{sha256}.{ext}`.
+    UrlTemplate(String),
+}
+
+/// Options for turning data URLs back into image files.
+#[derive(Clone)]
+pub struct ExtractOptions {
+    /// JSON Pointers (RFC 6901) of the fields that may hold data URLs, or arrays of them.
+    pub screenshot_pointers: Vec<String>,
+    pub target: ExtractTarget,
+    /// Receives the decoded images and the slimmed JSON files. Defaults to [`FsSink`].
+    pub sink: Arc<dyn OutputSink>,
+    /// Glob patterns of the files [`extract_dir`] picks up, relative to the input
+    /// directory. Defaults to [`crate::discovery::DEFAULT_INCLUDE_GLOB`].
+    pub include: Vec<String>,
+    /// Glob patterns of the files [`extract_dir`] leaves out.
+    pub exclude: Vec<String>,
+    /// Maximum number of files [`extract_dir`] extracts at once.
+    pub concurrency: usize,
+    /// Receives the result of every file [`extract_dir`] extracts as soon as it is ready.
+    pub on_file_result: Arc<FileResultHandler>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            screenshot_pointers: default_screenshot_pointers(),
+            target: ExtractTarget::RelativePath,
+            sink: Arc::new(FsSink),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            concurrency: DEFAULT_EXTRACT_CONCURRENCY,
+            on_file_result: Arc::new(ignore_file_results),
+        }
+    }
+}
+
+impl fmt::Debug for ExtractOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtractOptions")
+            .field("screenshot_pointers", &self.screenshot_pointers)
+            .field("target", &self.target)
+            .field("include", &self.include)
+            .field("exclude", &self.exclude)
+            .field("concurrency", &self.concurrency)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reverses [`crate::process_json_file`]: decodes every `data:` URL found in the selected
+/// fields, writes the image to `image_dir_path` and rewrites the field according to
+/// `options.target`. The slimmed JSON is written to `json_dir_path`. Images and JSON go
+/// through `options.sink`. Progress is reported
+/// to `events`; pass [`crate::ignore_events`] to drop it.
+///
+/// Called from within an async runtime, this fails with [`AppError::AsyncContext`].
+pub fn extract_json_file(
+    json_path: &Path,
+    image_dir_path: &Path,
+    json_dir_path: &Path,
+    options: &ExtractOptions,
+    events: &EventHandler,
+) -> FileProcessResult {
+    runtime::block_on(extract_file(
+        json_path,
+        image_dir_path,
+        json_dir_path,
+        options,
+        events,
+    ))
+    .unwrap_or_else(|e| FileProcessResult::Failed(json_path.display().to_string(), e))
+}
+
+/// Extracts every matching file below `input_dir`, [`ExtractOptions::concurrency`] at a time,
+/// like [`extract_json_file`] does for one file. The image and JSON output directories
+/// mirror the input layout; output directories inside `input_dir` are not walked. Results
+/// are returned sorted by path.
+///
+/// Called from within an async runtime, this fails with [`AppError::AsyncContext`].
+pub fn extract_dir(
+    input_dir: &Path,
+    image_dir_path: &Path,
+    json_dir_path: &Path,
+    options: &ExtractOptions,
+    events: &EventHandler,
+) -> Result<Vec<(PathBuf, FileProcessResult)>, AppError> {
+    let input_filter = InputFilter::new(&options.include, &options.exclude)?;
+    let job = DirJob {
+        input_dir,
+        image_dir: image_dir_path,
+        output_dir: json_dir_path,
+        input_filter: &input_filter,
+        mirror_images: true,
+        concurrency: options.concurrency,
+        events,
+        on_file_result: options.on_file_result.as_ref(),
+    };
+    runtime::block_on(rewrite_dir(
+        &job,
+        |json_path, image_dir, json_dir| async move {
+            extract_file(&json_path, &image_dir, &json_dir, options, events).await
+        },
+    ))?
+}
+
+async fn extract_file(
+    json_path: &Path,
+    image_dir_path: &Path,
+    json_dir_path: &Path,
+    options: &ExtractOptions,
+    events: &EventHandler,
+) -> FileProcessResult {
+    let job = FileJob {
+        json_path,
+        output_dir: json_dir_path,
+        sink: Arc::clone(&options.sink),
+        data_urls: Arc::default(),
+        explode_envelopes: false,
+        incremental: false,
+        events,
+        skip_reason: "No data URL found",
+    };
+    let extractor = Extractor {
+        json_path,
+        image_dir_path,
+        json_dir_path,
+        options,
+        events,
+    };
+    rewrite_file(&job, &extractor).await
+}
+
+/// Decodes the data URLs of the documents of one file.
+struct Extractor<'a> {
+    json_path: &'a Path,
+    image_dir_path: &'a Path,
+    json_dir_path: &'a Path,
+    options: &'a ExtractOptions,
+    events: &'a EventHandler,
+}
+
+impl DocumentRewriter for Extractor<'_> {
+    async fn rewrite(
+        &self,
+        document: &mut Value,
+        pointer_prefix: &str,
+        line: Option<usize>,
+    ) -> Vec<FieldReport> {
+        let Extractor {
+            json_path,
+            image_dir_path,
+            json_dir_path,
+            options,
+            events,
+        } = *self;
+        let mut reports = Vec::new();
+        for (pointer, data_url) in data_url_fields(document, &options.screenshot_pointers) {
+            let full_pointer = format!("{}{}", pointer_prefix, pointer);
+            // Decoding and writing the image are CPU-bound and blocking
+            let extracted = {
+                let json_path = json_path.to_path_buf();
+                let full_pointer = full_pointer.clone();
+                let image_dir_path = image_dir_path.to_path_buf();
+                let json_dir_path = json_dir_path.to_path_buf();
+                let options = options.clone();
+                run_blocking(move || {
+                    extract_field(
+                        &data_url,
+                        &json_path,
+                        line,
+                        &full_pointer,
+                        &image_dir_path,
+                        &json_dir_path,
+                        &options,
+                    )
+                })
+                .await
+            };
+            let status = match extracted {
+                Ok((image_path, replacement)) => {
+                    if let Some(value) = document.pointer_mut(&pointer) {
+                        *value = Value::String(replacement);
+                    }
+                    events(&ProgressEvent::Saved {
+                        file: json_path.to_path_buf(),
+                        kind: OutputKind::Image,
+                        path: image_path.clone(),
+                    });
+                    FieldStatus::Extracted { image_path }
+                }
+                Err(e) => {
+                    events(&ProgressEvent::Warning {
+                        file: json_path.to_path_buf(),
+                        line,
+                        pointer: full_pointer.clone(),
+                        message: format!("not extracted: {}", e),
+                    });
+                    FieldStatus::Failed(e)
+                }
+            };
+            reports.push(FieldReport {
+                line,
+                pointer: full_pointer,
+                attempts: 0,
+                status,
+            });
+        }
+        reports
+    }
+
+    fn concurrency(&self) -> usize {
+        self.options.concurrency
+    }
+}
+
+/// Collects `(pointer, data URL)` pairs for every selected field, expanding arrays.
+fn data_url_fields(document: &Value, pointers: &[String]) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    for pointer in pointers {
+        match document.pointer(pointer) {
+            Some(Value::String(s)) if s.starts_with("data:") => {
+                fields.push((pointer.clone(), s.clone()));
+            }
+            Some(Value::Array(items)) => {
+                for (index, item) in items.iter().enumerate() {
+                    if let Some(s) = item.as_str().filter(|s| s.starts_with("data:")) {
+                        fields.push((format!("{}/{}", pointer, index), s.to_string()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    fields
+}
+
+fn extract_field(
+    data_url: &str,
+    json_path: &Path,
+    line: Option<usize>,
+    pointer: &str,
+    image_dir_path: &Path,
+    json_dir_path: &Path,
+    options: &ExtractOptions,
+) -> Result<(PathBuf, String), AppError> {
+    let (mime_type, image_bytes) = decode_data_url(data_url)?;
+    let extension = extension_for_mime(&mime_type)
+        .or_else(|| infer::get(&image_bytes).map(|kind| kind.extension()))
+        .unwrap_or("bin");
+
+    let image_filename = derived_image_filename(json_path, line, pointer, extension)?;
+    let image_output_path = image_dir_path.join(&image_filename);
+    options
+        .sink
+        .write(OutputKind::Image, &image_output_path, &image_bytes)?;
+
+    let replacement = match &options.target {
+        ExtractTarget::RelativePath => relative_path(json_dir_path, &image_output_path)?,
+        ExtractTarget::UrlTemplate(template) => {
+            expand_url_template(template, &image_filename, &image_bytes)
+        }
+    };
+    Ok((image_output_path, replacement))
+}
+
+/// Fills in the [`FILENAME_PLACEHOLDER`], [`SHA256_PLACEHOLDER`] and
+/// [`EXTENSION_PLACEHOLDER`] of a URL template for an image.
+pub(crate) fn expand_url_template(template: &str, image_filename: &str, bytes: &[u8]) -> String {
+    let mut url = template.replace(FILENAME_PLACEHOLDER, image_filename);
+    if url.contains(SHA256_PLACEHOLDER) {
+        url = url.replace(SHA256_PLACEHOLDER, &sha256_hex(bytes));
+    }
+    if url.contains(EXTENSION_PLACEHOLDER) {
+        url = url.replace(
+            EXTENSION_PLACEHOLDER,
+            image_extension(image_filename, bytes),
+        );
+    }
+    url
+}
+
+/// Hex SHA-256 of `bytes`.
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Extension of an image file name, or one detected from the bytes if the name has none.
+pub(crate) fn image_extension<'a>(image_filename: &'a str, bytes: &[u8]) -> &'a str {
+    Path::new(image_filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .or_else(|| infer::get(bytes).map(|kind| kind.extension()))
+        .unwrap_or("bin")
+}
+
+/// Splits a `data:<mime>;base64,<payload>` URL into its MIME type and decoded bytes.
+pub fn decode_data_url(data_url: &str) -> Result<(String, Vec<u8>), AppError> {
+    let invalid = |reason: String| AppError::InvalidDataUrl {
+        reason,
+        source: None,
+    };
+    let (header, payload) = data_url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(','))
+        .ok_or_else(|| invalid("Malformed data URL: missing ',' separator".to_string()))?;
+    let mut header_parts = header.split(';');
+    let mime_type = match header_parts.next() {
+        Some(mime) if !mime.is_empty() => mime.to_string(),
+        _ => "application/octet-stream".to_string(),
+    };
+    if !header_parts.any(|param| param.eq_ignore_ascii_case("base64")) {
+        return Err(invalid(format!(
+            "Data URL with MIME type {} is not base64 encoded",
+            mime_type
+        )));
+    }
+
+    let image_bytes =
+        general_purpose::STANDARD
+            .decode(payload)
+            .map_err(|e| AppError::InvalidDataUrl {
+                reason: "Failed to decode base64 data URL".to_string(),
+                source: Some(e),
+            })?;
+    if image_bytes.is_empty() {
+        return Err(invalid("Data URL holds no data".to_string()));
+    }
+    Ok((mime_type, image_bytes))
+}
+
+/// File extension conventionally used for an image MIME type.
+pub fn extension_for_mime(mime_type: &str) -> Option<&'static str> {
+    match mime_type.to_ascii_lowercase().as_str() {
+        "image/png" => Some("png"),
+        "image/jpeg" | "image/jpg" => Some("jpg"),
+        "image/webp" => Some("webp"),
+        "image/gif" => Some("gif"),
+        "image/avif" => Some("avif"),
+        "image/bmp" => Some("bmp"),
+        "image/tiff" => Some("tif"),
+        "image/svg+xml" => Some("svg"),
+        "image/x-icon" | "image/vnd.microsoft.icon" => Some("ico"),
+        _ => None,
+    }
+}
+
+/// Image MIME type conventionally named by a file extension; the reverse of
+/// [`extension_for_mime`].
+pub fn mime_for_extension(extension: &str) -> Option<&'static str> {
+    match extension.to_ascii_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "webp" => Some("image/webp"),
+        "gif" => Some("image/gif"),
+        "avif" => Some("image/avif"),
+        "bmp" => Some("image/bmp"),
+        "tif" | "tiff" => Some("image/tiff"),
+        "svg" => Some("image/svg+xml"),
+        "ico" => Some("image/vnd.microsoft.icon"),
+        _ => None,
+    }
+}
+
+/// Path of `target` relative to the directory `base`, joined with `/` so it can be used
+/// as a URL reference in the output JSON.
+pub(crate) fn relative_path(base: &Path, target: &Path) -> Result<String, AppError> {
+    let absolute = |path: &Path| {
+        std::path::absolute(path)
+            .map_err(|e| AppError::InvalidPath(format!("Failed to resolve {:?}: {}", path, e)))
+    };
+    let base = absolute(base)?;
+    let target = absolute(target)?;
+
+    let base_components: Vec<Component> = base.components().collect();
+    let target_components: Vec<Component> = target.components().collect();
+    let common = base_components
+        .iter()
+        .zip(&target_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        // Different roots (e.g. drive letters): no relative path exists
+        return Err(AppError::InvalidPath(format!(
+            "No relative path from {:?} to {:?}",
+            base, target
+        )));
+    }
+
+    let mut parts: Vec<String> = vec!["..".to_string(); base_components.len() - common];
+    parts.extend(
+        target_components[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().to_string()),
+    );
+    Ok(parts.join("/"))
+}
@@ -0,0 +1,371 @@
+use reqwest::{
+    Client,
+    header::{CONTENT_TYPE, HeaderMap, RETRY_AFTER},
+};
+use std::{
+    fs,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::Semaphore;
+use url::Url;
+
+use crate::{AppError, RateLimiter, runtime::run_blocking};
+
+/// Timeout for a whole screenshot request made by [`HttpFetcher::with_defaults`].
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// --- Retry policy ---
+/// How failed downloads are retried. Only transient failures are retried, see
+/// [`AppError::is_transient`]: timeouts, connection and body errors, and the HTTP
+/// statuses 408, 425, 429, 500, 502, 503 and 504.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying.
+    pub max_retries: u32,
+    /// Delay before the first retry. It doubles with every further retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for a single delay, including one requested through `Retry-After`.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (1-based): exponential backoff with jitter,
+    /// picked uniformly between half and all of the exponential delay.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff);
+        let millis = exponential.as_millis() as u64;
+        Duration::from_millis(fastrand::u64(millis / 2..=millis))
+    }
+}
+
+// --- Fetchers ---
+/// A screenshot as delivered by a [`ScreenshotFetcher`].
+#[derive(Debug, Clone)]
+pub struct FetchedImage {
+    pub bytes: Vec<u8>,
+    /// MIME type reported by the source (e.g. the `Content-Type` header), if any.
+    pub content_type: Option<String>,
+}
+
+/// Future returned by [`ScreenshotFetcher::fetch`].
+pub type FetchFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<FetchedImage, AppError>> + Send + 'a>>;
+
+/// Where screenshots come from. A call to [`ScreenshotFetcher::fetch`] is a single attempt;
+/// retries, rate limiting and the concurrency limit are applied around it by the caller.
+///
+/// Fetchers run on the async runtime: a fetcher that has to block (e.g. on file I/O)
+/// should do so on the runtime's blocking pool.
+///
+/// ```
+/// use fcjp::{AppError, FetchFuture, FetchedImage, ScreenshotFetcher};
+///
+/// struct Fixture(Vec<u8>);
+///
+/// impl ScreenshotFetcher for Fixture {
+///     fn fetch<'a>(&'a self, _url: &'a str) -> FetchFuture<'a> {
+///         Box::pin(async move {
+///             Ok(FetchedImage { bytes: self.0.clone(), content_type: None })
+///         })
+///     }
+/// }
+/// ```
+pub trait ScreenshotFetcher: Send + Sync {
+    fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a>;
+}
+
+/// Downloads screenshots over HTTP(S) with `reqwest`'s async client.
+#[derive(Debug, Clone)]
+pub struct HttpFetcher {
+    http_client: Client,
+}
+
+impl HttpFetcher {
+    pub fn new(http_client: Client) -> Self {
+        HttpFetcher { http_client }
+    }
+
+    /// A fetcher with the `ScreenshotProcessor` user agent and a 60 second timeout.
+    pub fn with_defaults() -> Result<Self, AppError> {
+        let http_client = Client::builder()
+            .user_agent(format!("ScreenshotProcessor/{}", env!("CARGO_PKG_VERSION")))
+            .timeout(DEFAULT_TIMEOUT)
+            .build()
+            .map_err(AppError::HttpClient)?;
+        Ok(HttpFetcher::new(http_client))
+    }
+}
+
+impl ScreenshotFetcher for HttpFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        Box::pin(self.download(url))
+    }
+}
+
+impl HttpFetcher {
+    async fn download(&self, url: &str) -> Result<FetchedImage, AppError> {
+        let network_error = |source| AppError::Network {
+            url: url.to_string(),
+            source,
+        };
+        let response = self
+            .http_client
+            .get(url)
+            .send()
+            .await
+            .map_err(network_error)?;
+
+        if let Err(source) = response.error_for_status_ref() {
+            return Err(AppError::Http {
+                url: url.to_string(),
+                status: response.status(),
+                retry_after: retry_after(response.headers()),
+                source,
+            });
+        }
+
+        let content_type = content_type(response.headers());
+        // Converting the body takes over its buffer instead of copying it
+        let bytes: Vec<u8> = response.bytes().await.map_err(network_error)?.into();
+        fetched_image(url, bytes, content_type)
+    }
+}
+
+/// Downloads screenshots with `reqwest`'s blocking client, on the runtime's blocking pool.
+/// Backs [`crate::process_json_file`], which predates the async client.
+pub(crate) struct BlockingHttpFetcher {
+    http_client: reqwest::blocking::Client,
+}
+
+impl BlockingHttpFetcher {
+    pub(crate) fn new(http_client: reqwest::blocking::Client) -> Self {
+        BlockingHttpFetcher { http_client }
+    }
+}
+
+impl ScreenshotFetcher for BlockingHttpFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        let http_client = self.http_client.clone();
+        let url = url.to_string();
+        Box::pin(run_blocking(move || download_blocking(&http_client, &url)))
+    }
+}
+
+fn download_blocking(
+    http_client: &reqwest::blocking::Client,
+    url: &str,
+) -> Result<FetchedImage, AppError> {
+    let network_error = |source| AppError::Network {
+        url: url.to_string(),
+        source,
+    };
+    let response = http_client.get(url).send().map_err(network_error)?;
+
+    if let Err(source) = response.error_for_status_ref() {
+        return Err(AppError::Http {
+            url: url.to_string(),
+            status: response.status(),
+            retry_after: retry_after(response.headers()),
+            source,
+        });
+    }
+
+    let content_type = content_type(response.headers());
+    let bytes: Vec<u8> = response.bytes().map_err(network_error)?.into();
+    fetched_image(url, bytes, content_type)
+}
+
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+fn fetched_image(
+    url: &str,
+    bytes: Vec<u8>,
+    content_type: Option<String>,
+) -> Result<FetchedImage, AppError> {
+    if bytes.is_empty() {
+        return Err(AppError::EmptyBody {
+            url: url.to_string(),
+        });
+    }
+    Ok(FetchedImage {
+        bytes,
+        content_type,
+    })
+}
+
+/// Reads screenshots referenced by `file://` URLs from the local filesystem.
+#[derive(Debug, Clone, Default)]
+pub struct FileFetcher;
+
+impl ScreenshotFetcher for FileFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move {
+            let parsed_url = parse_url(url)?;
+            if parsed_url.scheme() != "file" {
+                return Err(AppError::UnsupportedUrl {
+                    url: url.to_string(),
+                    reason: "not a file:// URL",
+                });
+            }
+            let path = parsed_url
+                .to_file_path()
+                .map_err(|()| AppError::UnsupportedUrl {
+                    url: url.to_string(),
+                    reason: "does not name a local file",
+                })?;
+            read_image(url, path).await
+        })
+    }
+}
+
+/// Serves screenshots from an offline copy of their hosts: `https://host:port/a/b.png` is
+/// read from `<root>/host:port/a/b.png`, the layout `wget --mirror` produces (a URL ending in
+/// `/` maps to `index.html`). Query strings and fragments are ignored, so signed URLs that
+/// only differ in their tokens share one file.
+#[derive(Debug, Clone)]
+pub struct MirrorFetcher {
+    root: PathBuf,
+}
+
+impl MirrorFetcher {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        MirrorFetcher { root: root.into() }
+    }
+
+    /// Path of the mirrored copy of `url`.
+    pub fn mirror_path(&self, url: &str) -> Result<PathBuf, AppError> {
+        let parsed_url = parse_url(url)?;
+        let host = parsed_url
+            .host_str()
+            .ok_or_else(|| AppError::UnsupportedUrl {
+                url: url.to_string(),
+                reason: "has no host to look up in the mirror",
+            })?;
+        let mut path = self.root.join(match parsed_url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        });
+        // The URL parser has already resolved '.' and '..' segments
+        let segments: Vec<&str> = parsed_url
+            .path_segments()
+            .map(|segments| segments.collect())
+            .unwrap_or_default();
+        for segment in &segments {
+            path.push(segment);
+        }
+        if segments.last().is_none_or(|segment| segment.is_empty()) {
+            path.push("index.html");
+        }
+        Ok(path)
+    }
+}
+
+impl ScreenshotFetcher for MirrorFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move { read_image(url, self.mirror_path(url)?).await })
+    }
+}
+
+fn parse_url(url: &str) -> Result<Url, AppError> {
+    Url::parse(url).map_err(|_| AppError::UnsupportedUrl {
+        url: url.to_string(),
+        reason: "not a valid URL",
+    })
+}
+
+async fn read_image(url: &str, path: PathBuf) -> Result<FetchedImage, AppError> {
+    let bytes =
+        run_blocking(move || fs::read(&path).map_err(|source| AppError::Read { path, source }))
+            .await?;
+    if bytes.is_empty() {
+        return Err(AppError::EmptyBody {
+            url: url.to_string(),
+        });
+    }
+    Ok(FetchedImage {
+        bytes,
+        content_type: None,
+    })
+}
+
+/// Fetches `url`, retrying transient failures according to `retry_policy`. Every attempt
+/// first waits for `rate_limiter` and a permit from `download_permits`, and is then announced
+/// to `on_attempt` with its 1-based number. The permit is not held while backing off.
+/// Returns the outcome together with the number of attempts made.
+pub(crate) async fn fetch_with_retry(
+    fetcher: &dyn ScreenshotFetcher,
+    url: &str,
+    retry_policy: &RetryPolicy,
+    rate_limiter: &RateLimiter,
+    download_permits: &Semaphore,
+    on_attempt: impl Fn(u32),
+) -> (Result<FetchedImage, AppError>, u32) {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        rate_limiter.acquire_async(url).await;
+        let fetched = {
+            let _permit = download_permits
+                .acquire()
+                .await
+                .expect("download semaphore is never closed");
+            on_attempt(attempts);
+            fetcher.fetch(url).await
+        };
+        let error = match fetched {
+            Ok(fetched) => return (Ok(fetched), attempts),
+            Err(error) => error,
+        };
+        if !error.is_transient() || attempts > retry_policy.max_retries {
+            return (Err(error), attempts);
+        }
+        let delay = match error.retry_after() {
+            Some(requested) => requested.min(retry_policy.max_backoff),
+            None => retry_policy.backoff(attempts),
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after)
+}
+
+/// Parses a `Retry-After` header value: either delay-seconds or an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let retry_at = httpdate::parse_http_date(value).ok()?;
+    // A date in the past means "retry now"
+    Some(
+        retry_at
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
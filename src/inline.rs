@@ -0,0 +1,315 @@
+use futures_util::future::join_all;
+use serde_json::Value;
+use std::{path::Path, sync::Arc};
+use tokio::sync::Semaphore;
+
+use crate::{
+    AppError, RateLimiter, RetryPolicy,
+    data_url::data_url,
+    default_screenshot_pointers,
+    events::{EventHandler, ProgressEvent, ignore_events},
+    extract::sha256_hex,
+    fetch::{FetchedImage, ScreenshotFetcher, fetch_with_retry},
+    naming::{ImageNaming, ImageOrigin, OnTypeMismatch, resolve_mime_type},
+    runtime::run_blocking,
+    validate::ImageValidation,
+};
+
+/// Screenshots downloaded at once when no other limit is configured.
+pub const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 16;
+
+// --- In-memory inlining ---
+/// Which fields are inlined and how screenshots are fetched.
+#[derive(Debug, Clone)]
+pub struct InlineOptions {
+    /// JSON Pointers (RFC 6901) of the fields holding screenshot URLs. Every field that
+    /// resolves to a URL is fetched and replaced in place with a data URL; a field that
+    /// resolves to an array has each of its URL entries inlined.
+    pub screenshot_pointers: Vec<String>,
+    /// How failed fetches are retried.
+    pub retry: RetryPolicy,
+    /// Requests-per-second limits applied to every fetch attempt, retries included.
+    /// Clones share the limiter, so one instance throttles all worker threads together.
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Bounds the number of fetches in flight; each attempt holds one permit. Clones share
+    /// the semaphore, so the bound applies to all documents processed with them together.
+    pub download_permits: Arc<Semaphore>,
+    /// How fetched images are named, see [`InlinedImage::filename`].
+    pub naming: ImageNaming,
+    /// Which type wins when the URL extension, `Content-Type` and content of an image
+    /// disagree, see [`InlinedImage::mime_type`].
+    pub on_type_mismatch: OnTypeMismatch,
+    /// Checks that fetched payloads are images before they are inlined; `None` accepts
+    /// anything.
+    pub validation: Option<ImageValidation>,
+}
+
+impl Default for InlineOptions {
+    fn default() -> Self {
+        InlineOptions {
+            screenshot_pointers: default_screenshot_pointers(),
+            retry: RetryPolicy::default(),
+            rate_limiter: Arc::new(RateLimiter::default()),
+            download_permits: Arc::new(Semaphore::new(DEFAULT_DOWNLOAD_CONCURRENCY)),
+            naming: ImageNaming::default(),
+            on_type_mismatch: OnTypeMismatch::default(),
+            validation: Some(ImageValidation::default()),
+        }
+    }
+}
+
+/// Outcome of inlining one screenshot field in memory.
+#[derive(Debug)]
+pub struct InlinedField {
+    /// JSON Pointer of the field within the document.
+    pub pointer: String,
+    /// Fetch attempts made for the field, including retries (0 if nothing was fetched).
+    pub attempts: u32,
+    pub outcome: InlineOutcome,
+}
+
+#[derive(Debug)]
+pub enum InlineOutcome {
+    /// The field now holds a data URL of the image.
+    Inlined(InlinedImage),
+    /// The field already held a data URL, so nothing was fetched.
+    UpToDate,
+    /// The fetched payload is not an acceptable image ([`AppError::InvalidImage`]); the
+    /// field was left untouched.
+    Rejected(AppError),
+    /// The field was left untouched.
+    Failed(AppError),
+}
+
+/// A screenshot that was fetched and inlined, for callers that want to keep the image.
+#[derive(Debug, Clone)]
+pub struct InlinedImage {
+    /// The URL the field held before it was inlined.
+    pub source_url: String,
+    /// Sanitized file name for the image, chosen according to [`InlineOptions::naming`]:
+    /// by default the last URL path segment, or one derived from the document's file name.
+    pub filename: String,
+    /// MIME type of the image: by default the one sniffed from its bytes, see
+    /// [`InlineOptions::on_type_mismatch`].
+    pub mime_type: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Fetches every screenshot field of `document` with `fetcher` and replaces it with a data
+/// URL, without touching the filesystem. `source_path` is the name of the file the document
+/// came from (e.g. `page.json`); it only names images whose URL has no usable file name.
+///
+/// The fields are fetched concurrently, up to `options.download_permits` at a time, and
+/// encoded on the runtime's blocking pool. The document is treated as a single Firecrawl
+/// document; use [`crate::crawl_envelope_pages_mut`] to inline the pages of a crawl/batch
+/// envelope.
+pub async fn inline_value(
+    document: &mut Value,
+    fetcher: &dyn ScreenshotFetcher,
+    options: &InlineOptions,
+    source_path: &Path,
+) -> Vec<InlinedField> {
+    let source = DocumentSource {
+        path: source_path,
+        pointer_prefix: "",
+        line: None,
+    };
+    inline_value_at(document, fetcher, options, &source, &ignore_events, false).await
+}
+
+/// Where a document comes from.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DocumentSource<'a> {
+    /// The file holding the document.
+    pub path: &'a Path,
+    /// Locates a page of an envelope within its file; empty for other documents.
+    pub pointer_prefix: &'a str,
+    /// 1-based line number when the document comes from a JSON Lines file.
+    pub line: Option<usize>,
+}
+
+/// [`inline_value`] for a document located within its file by `source`. Reported pointers
+/// include its pointer prefix. Fetches are reported to `events`.
+///
+/// With `defer_data_urls` the fields are left untouched and no data URL is built; the
+/// caller stores the images (see [`crate::data_url::DataUrls`]).
+pub(crate) async fn inline_value_at(
+    document: &mut Value,
+    fetcher: &dyn ScreenshotFetcher,
+    options: &InlineOptions,
+    source: &DocumentSource<'_>,
+    events: &EventHandler,
+    defer_data_urls: bool,
+) -> Vec<InlinedField> {
+    let screenshot_fields = screenshot_fields(document, &options.screenshot_pointers);
+    let inlined = join_all(
+        screenshot_fields
+            .into_iter()
+            .map(|field| inline_field(field, fetcher, options, source, events, defer_data_urls)),
+    )
+    .await;
+
+    let mut inlined_fields = Vec::with_capacity(inlined.len());
+    for (relative_pointer, inlined_field, data_url) in inlined {
+        if let (Some(data_url), Some(value)) = (data_url, document.pointer_mut(&relative_pointer)) {
+            *value = Value::String(data_url);
+        }
+        inlined_fields.push(inlined_field);
+    }
+    inlined_fields
+}
+
+/// Fetches and encodes one field. Returns its pointer relative to the document, the outcome
+/// and the data URL to store in the field, if any.
+async fn inline_field(
+    field: ScreenshotField,
+    fetcher: &dyn ScreenshotFetcher,
+    options: &InlineOptions,
+    source: &DocumentSource<'_>,
+    events: &EventHandler,
+    defer_data_urls: bool,
+) -> (String, InlinedField, Option<String>) {
+    let full_pointer = format!("{}{}", source.pointer_prefix, field.pointer);
+    if field.url.starts_with("data:") {
+        let inlined_field = InlinedField {
+            pointer: full_pointer,
+            attempts: 0,
+            outcome: InlineOutcome::UpToDate,
+        };
+        return (field.pointer, inlined_field, None);
+    }
+
+    let (fetched, attempts) = fetch_with_retry(
+        fetcher,
+        &field.url,
+        &options.retry,
+        &options.rate_limiter,
+        &options.download_permits,
+        |attempt| {
+            events(&ProgressEvent::Downloading {
+                file: source.path.to_path_buf(),
+                pointer: full_pointer.clone(),
+                url: field.url.clone(),
+                attempt,
+            })
+        },
+    )
+    .await;
+    let image = match fetched {
+        Ok(fetched) => {
+            events(&ProgressEvent::Downloaded {
+                file: source.path.to_path_buf(),
+                pointer: full_pointer.clone(),
+                bytes: fetched.bytes.len(),
+            });
+            let origin = ImageOrigin {
+                url: &field.url,
+                indexed: field.indexed,
+                json_path: source.path,
+                line: source.line,
+                pointer: &full_pointer,
+            };
+            name_image(&origin, options, fetched).await
+        }
+        Err(e) => Err(e),
+    };
+    let (outcome, data_url) = match image {
+        Ok(image) if defer_data_urls => (InlineOutcome::Inlined(image), None),
+        Ok(image) => {
+            let (image, data_url) = run_blocking(move || {
+                let data_url = data_url(&image.mime_type, &image.bytes);
+                (image, data_url)
+            })
+            .await;
+            (InlineOutcome::Inlined(image), Some(data_url))
+        }
+        Err(e @ AppError::InvalidImage { .. }) => (InlineOutcome::Rejected(e), None),
+        Err(e) => (InlineOutcome::Failed(e), None),
+    };
+    let inlined_field = InlinedField {
+        pointer: full_pointer,
+        attempts,
+        outcome,
+    };
+    (field.pointer, inlined_field, data_url)
+}
+
+/// A screenshot URL found in a document, located by its pointer relative to the document.
+struct ScreenshotField {
+    pointer: String,
+    url: String,
+    /// The URL is one entry of an array; its image gets an index-based name.
+    indexed: bool,
+}
+
+/// Collects the screenshot URLs selected by `pointers`. A field holds either one URL or,
+/// like `actions.screenshots`, an array of URLs.
+fn screenshot_fields(document: &Value, pointers: &[String]) -> Vec<ScreenshotField> {
+    let mut screenshot_fields = Vec::new();
+    for pointer in pointers {
+        match document.pointer(pointer) {
+            Some(Value::String(url)) if is_screenshot_url(url) => {
+                screenshot_fields.push(ScreenshotField {
+                    pointer: pointer.clone(),
+                    url: url.clone(),
+                    indexed: false,
+                });
+            }
+            Some(Value::Array(items)) => {
+                for (index, item) in items.iter().enumerate() {
+                    if let Some(url) = item.as_str().filter(|url| is_screenshot_url(url)) {
+                        screenshot_fields.push(ScreenshotField {
+                            pointer: format!("{}/{}", pointer, index),
+                            url: url.to_string(),
+                            indexed: true,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    screenshot_fields
+}
+
+fn is_screenshot_url(url: &str) -> bool {
+    !url.is_empty() && url != "null"
+}
+
+/// Validates a fetched image, determines its type and names it according to `options`,
+/// hashing it on the blocking pool if the name needs its SHA-256.
+async fn name_image(
+    origin: &ImageOrigin<'_>,
+    options: &InlineOptions,
+    fetched: FetchedImage,
+) -> Result<InlinedImage, AppError> {
+    let FetchedImage {
+        bytes,
+        content_type,
+    } = fetched;
+    if let Some(validation) = &options.validation {
+        validation.check(origin.url, &bytes)?;
+    }
+    let mime_type = resolve_mime_type(
+        origin.url,
+        origin.url_mime_type(),
+        content_type.as_deref(),
+        &bytes,
+        options.on_type_mismatch,
+    )?;
+    let (sha256, bytes) = if options.naming.uses_hash() {
+        let (sha256, bytes) = run_blocking(move || (sha256_hex(&bytes), bytes)).await;
+        (Some(sha256), bytes)
+    } else {
+        (None, bytes)
+    };
+    let filename = options
+        .naming
+        .file_name(origin, &mime_type, sha256.as_deref())?;
+    Ok(InlinedImage {
+        source_url: origin.url.to_string(),
+        filename,
+        mime_type,
+        bytes,
+    })
+}
@@ -1,295 +1,205 @@
-use base64::{Engine as _, engine::general_purpose};
-use reqwest::blocking::Client;
-use serde_json::Value;
 use std::{
-    error::Error,
-    fs,
-    io::{self},
     path::{Path, PathBuf},
+    sync::Arc,
 };
-use url::Url;
+
+mod data_url;
+pub mod discovery;
+mod document;
+mod error;
+pub mod events;
+pub mod extract;
+pub mod fetch;
+mod inline;
+pub mod naming;
+mod processor;
+pub mod rate_limit;
+pub mod resize;
+mod runtime;
+pub mod sink;
+pub mod split;
+pub mod transcode;
+pub mod validate;
+
+pub use discovery::{InputFilter, discover_input_files, mirrored_output_dir};
+pub use document::{crawl_envelope_pages_mut, is_jsonl_path};
+pub use error::AppError;
+pub use events::{
+    EventHandler, FileResultHandler, ProgressEvent, ignore_events, ignore_file_results,
+};
+pub use extract::{ExtractOptions, ExtractTarget, extract_dir, extract_json_file};
+pub use fetch::{
+    FetchFuture, FetchedImage, FileFetcher, HttpFetcher, MirrorFetcher, RetryPolicy,
+    ScreenshotFetcher,
+};
+pub use inline::{
+    DEFAULT_DOWNLOAD_CONCURRENCY, InlineOptions, InlineOutcome, InlinedField, InlinedImage,
+    inline_value,
+};
+pub use naming::{ImageNaming, OnCollision, OnTypeMismatch, sanitize_filename};
+pub use processor::{Deduplication, Processor, ProcessorConfig, ScreenshotTarget};
+pub use rate_limit::RateLimiter;
+pub use resize::{Resize, Thumbnail};
+pub use sink::{FsSink, MemorySink, OutputKind, OutputSink, TarSink, WriteOutput, ZipSink};
+pub use split::Split;
+pub use transcode::{Transcode, TranscodeFormat};
+pub use validate::ImageValidation;
 
 // --- Default names for output subdirectories ---
 pub const IMAGE_DIR_NAME: &str = "images";
 pub const BASE64_DIR_NAME: &str = "base64";
+pub const EXTRACTED_DIR_NAME: &str = "extracted";
 
-// --- Custom Error Type ---
-#[derive(Debug)]
-pub struct AppError(pub String);
+// --- Screenshot field selection ---
+/// JSON Pointer (RFC 6901) of the field that holds the screenshot URL in a plain Firecrawl document.
+pub const DEFAULT_SCREENSHOT_POINTER: &str = "/screenshot";
 
-impl std::fmt::Display for AppError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
-impl Error for AppError {}
-impl From<&str> for AppError {
-    fn from(s: &str) -> Self {
-        AppError(s.to_string())
-    }
-}
-impl From<String> for AppError {
-    fn from(s: String) -> Self {
-        AppError(s)
-    }
-}
-impl From<io::Error> for AppError {
-    fn from(err: io::Error) -> Self {
-        AppError(format!("IO Error: {}", err))
-    }
-}
-impl From<serde_json::Error> for AppError {
-    fn from(err: serde_json::Error) -> Self {
-        AppError(format!("JSON Error: {}", err))
-    }
-}
-impl From<reqwest::Error> for AppError {
-    fn from(err: reqwest::Error) -> Self {
-        AppError(format!("HTTP Request Error: {}", err))
-    }
+/// JSON Pointer of the array of extra captures taken by Firecrawl browser actions.
+pub const ACTIONS_SCREENSHOTS_POINTER: &str = "/actions/screenshots";
+
+/// Field of a crawl/batch status response that holds the array of page documents.
+pub const ENVELOPE_DATA_KEY: &str = "data";
+
+/// Extension of JSON Lines files, which are processed one document per line.
+pub const JSONL_EXTENSION: &str = "jsonl";
+
+/// The screenshot fields looked at when none are configured: the root `screenshot` field
+/// and the `actions.screenshots` array.
+pub fn default_screenshot_pointers() -> Vec<String> {
+    vec![
+        DEFAULT_SCREENSHOT_POINTER.to_string(),
+        ACTIONS_SCREENSHOTS_POINTER.to_string(),
+    ]
 }
-impl From<url::ParseError> for AppError {
-    fn from(err: url::ParseError) -> Self {
-        AppError(format!("URL Parse Error: {}", err))
+
+/// Checks that `pointer` is a syntactically valid JSON Pointer (RFC 6901).
+pub fn validate_json_pointer(pointer: &str) -> Result<(), AppError> {
+    if !pointer.is_empty() && !pointer.starts_with('/') {
+        return Err(AppError::InvalidPointer {
+            pointer: pointer.to_string(),
+            reason: "must be empty or start with '/'",
+        });
+    }
+    // '~' may only appear as the escape sequences '~0' and '~1'
+    let mut chars = pointer.chars();
+    while let Some(c) = chars.next() {
+        if c == '~' && !matches!(chars.next(), Some('0') | Some('1')) {
+            return Err(AppError::InvalidPointer {
+                pointer: pointer.to_string(),
+                reason: "'~' must be followed by '0' or '1'",
+            });
+        }
     }
+    Ok(())
 }
 
 // --- Processing Result Enum for each file ---
 #[derive(Debug)]
 pub enum FileProcessResult {
-    Success,
+    Success(Vec<FieldReport>),
     Skipped(String),
-    Failed(String, String), // file_name_for_log, error_message
+    /// Nothing needed doing: every screenshot was already inlined, or (in incremental
+    /// mode) the output is newer than the input.
+    UpToDate(String),
+    /// Nothing was written because no field could be rewritten and at least one downloaded
+    /// payload was [rejected](FieldStatus::Rejected), e.g. the error page of an expired URL.
+    /// The reports of the fields say why each one was left untouched.
+    Rejected(String, Vec<FieldReport>), // file_name_for_log, reports
+    /// Nothing could be rewritten: the file could not be read or written, or every field
+    /// in it failed. The error is the first one.
+    Failed(String, AppError), // file_name_for_log, error
+}
+
+/// Outcome of inlining a single screenshot field.
+#[derive(Debug)]
+pub struct FieldReport {
+    /// 1-based line number for documents read from a JSON Lines file.
+    pub line: Option<usize>,
+    /// JSON Pointer of the field within the document.
+    pub pointer: String,
+    /// Download attempts made for the field, including retries (0 if nothing was downloaded).
+    pub attempts: u32,
+    pub status: FieldStatus,
+}
+
+#[derive(Debug)]
+pub enum FieldStatus {
+    /// The image was saved to `image_path` and the field now holds a data URL.
+    Inlined { image_path: PathBuf },
+    /// The image was saved to `image_path` and the field now references it by path or URL,
+    /// see [`ScreenshotTarget`].
+    Linked { image_path: PathBuf },
+    /// The data URL was decoded to `image_path` and the field now references the image.
+    Extracted { image_path: PathBuf },
+    /// The field already held a data URL, so nothing was downloaded.
+    UpToDate,
+    /// The downloaded payload is not an acceptable image (see [`ImageValidation`]), so
+    /// nothing was saved and the field was left untouched.
+    Rejected(AppError),
+    /// The field was left untouched.
+    Failed(AppError),
 }
 
-/// Processes a single JSON file.
+/// Processes a single JSON file, inlining the root `screenshot` field and `actions.screenshots` entries.
+/// Shorthand for a default [`Processor`] downloading with the blocking `http_client`; build
+/// one with [`Processor::builder`] to change options or to receive progress events.
+///
+/// `show_progress` is deprecated and ignored: the library does not print anything. It is
+/// only kept so that existing callers still compile.
+///
+/// Called from within an async runtime, this fails with [`AppError::AsyncContext`].
 pub fn process_json_file(
-    json_path: &PathBuf,
+    json_path: &Path,
     image_dir_path: &Path,
     base64_dir_path: &Path,
-    http_client: &Client,
-    show_progress: bool,
+    http_client: &reqwest::blocking::Client,
+    _show_progress: bool,
 ) -> FileProcessResult {
-    let file_name_os_str = match json_path.file_name() {
-        Some(name) => name,
-        None => {
-            return FileProcessResult::Failed(
-                "UnknownFile".to_string(),
-                format!("Could not get file name from path: {:?}", json_path),
-            );
-        }
-    };
-    let log_file_name = file_name_os_str.to_string_lossy().to_string(); // For logging, even if not perfect UTF-8
-
-    if !show_progress {
-        println!("Processing file: {}", log_file_name);
-    }
-
-    let content = match fs::read_to_string(json_path) {
-        Ok(c) => c,
-        Err(e) => {
-            return FileProcessResult::Failed(
-                log_file_name,
-                format!("Failed to read file content: {}", e),
-            );
-        }
-    };
-    let mut json_data: Value = match serde_json::from_str(&content) {
-        Ok(jd) => jd,
-        Err(e) => {
-            return FileProcessResult::Failed(log_file_name, format!("Failed to parse JSON: {}", e));
-        }
-    };
-
-    let screenshot_url_opt = json_data
-        .get("screenshot")
-        .and_then(Value::as_str)
-        .map(String::from);
-
-    let screenshot_url = match screenshot_url_opt {
-        Some(url) if !url.is_empty() && url != "null" => url,
-        _ => {
-            let skip_msg = format!("No valid screenshot URL found in {}", log_file_name);
-            if !show_progress {
-                println!("  [SKIP] {}", skip_msg);
-            }
-            return FileProcessResult::Skipped(skip_msg);
-        }
-    };
-    if !show_progress {
-        println!("  Screenshot URL: {}", screenshot_url);
-    }
-
-    if !show_progress {
-        println!("  Downloading image from {} ...", screenshot_url);
+    let processor = Processor::builder(image_dir_path, base64_dir_path)
+        .fetcher(Arc::new(fetch::BlockingHttpFetcher::new(
+            http_client.clone(),
+        )))
+        .build();
+    match processor {
+        Ok(processor) => processor.process_file(json_path),
+        Err(e) => FileProcessResult::Failed(json_path.display().to_string(), e),
     }
-    let response = match http_client.get(&screenshot_url).send() {
-        Ok(r) => r,
-        Err(e) => {
-            return FileProcessResult::Failed(
-                log_file_name,
-                format!("HTTP request failed for {}: {}", screenshot_url, e),
-            );
-        }
-    };
-
-    if let Err(e) = response.error_for_status_ref() {
-        return FileProcessResult::Failed(
-            log_file_name,
-            format!("HTTP error downloading {}: {}", screenshot_url, e),
-        );
-    }
-
-    let image_bytes = match response.bytes() {
-        Ok(b) => b.to_vec(),
-        Err(e) => {
-            return FileProcessResult::Failed(
-                log_file_name,
-                format!("Failed to get image bytes from {}: {}", screenshot_url, e),
-            );
-        }
-    };
-
-    if image_bytes.is_empty() {
-        return FileProcessResult::Failed(
-            log_file_name,
-            format!("Downloaded image from {} is empty", screenshot_url),
-        );
-    }
-    if !show_progress {
-        println!("  Download successful ({} bytes).", image_bytes.len());
-    }
-
-    // --- MODIFIED: Image filename extraction ---
-    let image_filename_to_save: String = match Url::parse(&screenshot_url) {
-        Ok(parsed_url) => {
-            if let Some(name) = parsed_url
-                .path_segments()
-                .and_then(|mut s| s.next_back())
-                .filter(|s| !s.is_empty())
-            {
-                name.to_string()
-            } else {
-                let warn_msg = format!(
-                    "[WARN] Could not determine filename from URL path segments: {}. Using JSON-derived name for {}.",
-                    screenshot_url, log_file_name
-                );
-                if show_progress {
-                    eprintln!("{}", warn_msg);
-                } else {
-                    println!("  {}", warn_msg);
-                }
-                let stem = match json_path.file_stem().and_then(|s| s.to_str()) {
-                    Some(s) => s,
-                    None => {
-                        return FileProcessResult::Failed(
-                            log_file_name,
-                            format!(
-                                "Could not get valid file stem from {:?} as fallback",
-                                json_path
-                            ),
-                        );
-                    }
-                };
-                format!("{}.png", stem)
-            }
-        }
-        Err(parse_err) => {
-            let warn_msg = format!(
-                "[WARN] Failed to parse screenshot URL '{}' for filename extraction: {}. Using JSON-derived name for {}.",
-                screenshot_url, parse_err, log_file_name
-            );
-            if show_progress {
-                eprintln!("{}", warn_msg);
-            } else {
-                println!("  {}", warn_msg);
-            }
-            let stem = match json_path.file_stem().and_then(|s| s.to_str()) {
-                Some(s) => s,
-                None => {
-                    return FileProcessResult::Failed(
-                        log_file_name,
-                        format!(
-                            "Could not get valid file stem from {:?} as fallback after URL parse error",
-                            json_path
-                        ),
-                    );
-                }
-            };
-            format!("{}.png", stem)
-        }
-    };
-
-    let image_output_path = image_dir_path.join(&image_filename_to_save);
-    if !show_progress {
-        println!("  Image will be saved as: {}", image_filename_to_save);
-    }
-
-    if let Err(e) = fs::write(&image_output_path, &image_bytes) {
-        return FileProcessResult::Failed(
-            log_file_name,
-            format!("Failed to save image to {:?}: {}", image_output_path, e),
-        );
-    }
-    if !show_progress {
-        println!("  Image saved to: {:?}", image_output_path);
-    }
-
-    let mime_type = match infer::get(&image_bytes) {
-        Some(kind) => {
-            if !show_progress {
-                println!("  Detected MIME type: {}", kind.mime_type());
-            }
-            kind.mime_type().to_string()
-        }
-        None => {
-            if !show_progress {
-                println!(
-                    "  [WARN] Could not infer MIME type. Defaulting to application/octet-stream."
-                );
-            }
-            "application/octet-stream".to_string()
-        }
-    };
-
-    let base64_encoded_image = general_purpose::STANDARD.encode(&image_bytes);
-    let data_url = format!("data:{};base64,{}", mime_type, base64_encoded_image);
-
-    let obj = match json_data.as_object_mut() {
-        Some(o) => o,
-        None => {
-            return FileProcessResult::Failed(
-                log_file_name,
-                "JSON root is not an object".to_string(),
-            );
-        }
-    };
-    obj.insert("screenshot".to_string(), Value::String(data_url));
-
-    let new_json_string = match serde_json::to_string_pretty(&json_data) {
-        Ok(s) => s,
-        Err(e) => {
-            return FileProcessResult::Failed(
-                log_file_name,
-                format!("Failed to serialize new JSON: {}", e),
-            );
-        }
-    };
-    // Use the original OsStr for the output JSON filename to handle non-UTF8 filenames correctly
-    let base64_json_output_path = base64_dir_path.join(file_name_os_str);
+}
 
-    if let Err(e) = fs::write(&base64_json_output_path, new_json_string) {
-        return FileProcessResult::Failed(
-            log_file_name,
-            format!(
-                "Failed to save base64 JSON to {:?}: {}",
-                base64_json_output_path, e
-            ),
-        );
-    }
-    if !show_progress {
-        println!("  Base64 JSON saved to: {:?}", base64_json_output_path);
-    }
+/// Builds an image file name from the JSON file stem, plus the line and field where they
+/// are needed to tell images apart: `page.png`, `pages-line3.png`, `crawl-data-0-screenshot.png`.
+/// The name is sanitized with [`sanitize_filename`].
+pub(crate) fn derived_image_filename(
+    json_path: &Path,
+    line: Option<usize>,
+    pointer: &str,
+    extension: &str,
+) -> Result<String, AppError> {
+    let stem = derived_image_stem(json_path, line, pointer)?;
+    Ok(sanitize_filename(&format!("{}.{}", stem, extension)))
+}
 
-    FileProcessResult::Success
+/// [`derived_image_filename`] without the extension, and not yet sanitized.
+pub(crate) fn derived_image_stem(
+    json_path: &Path,
+    line: Option<usize>,
+    pointer: &str,
+) -> Result<String, AppError> {
+    let stem = json_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| {
+            AppError::InvalidPath(format!(
+                "Could not get valid file stem from {:?} as fallback",
+                json_path
+            ))
+        })?;
+    let mut name = stem.to_string();
+    if let Some(line) = line {
+        name.push_str(&format!("-line{}", line));
+    }
+    if pointer != DEFAULT_SCREENSHOT_POINTER {
+        name.push_str(&pointer.replace('/', "-"));
+    }
+    Ok(name)
 }
@@ -1,26 +1,84 @@
-use clap::Parser;
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
-use rayon::prelude::*;
+use clap::{Args, Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
 use std::{
     error::Error,
     fs,
-    path::PathBuf,
-    sync::{
-        Arc,
-        atomic::{AtomicUsize, Ordering},
-    },
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 
 // Import functionality from our library
-use fcjp::{AppError, BASE64_DIR_NAME, FileProcessResult, IMAGE_DIR_NAME, process_json_file};
+use fcjp::{
+    AppError, BASE64_DIR_NAME, EXTRACTED_DIR_NAME, EventHandler, ExtractOptions, ExtractTarget,
+    FieldReport, FieldStatus, FileProcessResult, FileResultHandler, IMAGE_DIR_NAME, ImageNaming,
+    ImageValidation, MirrorFetcher, OnCollision, OnTypeMismatch, OutputKind, Processor,
+    ProgressEvent, RateLimiter, Resize, RetryPolicy, ScreenshotTarget, Split, Thumbnail, Transcode,
+    TranscodeFormat, extract_dir, validate_json_pointer,
+};
 
 // --- Command-Line Arguments Definition ---
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct CliArgs {
-    /// Directory containing the JSON files to process.
-    #[arg(short, long, value_name = "SOURCE_DIRECTORY")]
-    directory: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    inline: InlineArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Decode base64 data URLs back into image files and rewrite the fields to paths or URLs.
+    Extract(ExtractArgs),
+}
+
+/// Arguments shared by every mode: what to read and how to run.
+#[derive(Args, Debug)]
+struct InputArgs {
+    /// Directory containing the JSON files to process. Subdirectories are scanned recursively.
+    // Optional for the derive only: the inline arguments are also built when a subcommand
+    // runs, which would fail on a missing value. Clap still requires it in either mode.
+    #[arg(short, long, value_name = "SOURCE_DIRECTORY", required = true)]
+    directory: Option<PathBuf>,
+
+    /// Glob pattern (relative to the source directory) of files to process. Can be repeated.
+    /// Defaults to '**/*.{json,jsonl}'. JSON Lines files are processed one document per line.
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Glob pattern (relative to the source directory) of files to leave out. Can be repeated.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// JSON Pointer (RFC 6901) of a field holding a screenshot URL or an array of URLs,
+    /// e.g. '/data/screenshot'. Can be repeated. Defaults to '/screenshot' and '/actions/screenshots'.
+    #[arg(long = "field", value_name = "JSON_POINTER")]
+    fields: Vec<String>,
+
+    /// Number of concurrent jobs to run: screenshot downloads (and files) when inlining,
+    /// files when extracting.
+    #[arg(short, long, value_name = "NUM_JOBS", default_value_t = 4)]
+    concurrency: usize,
+
+    /// Display a progress bar.
+    #[arg(long)]
+    progress: bool,
+}
+
+/// Default mode: download screenshots and inline them as data URLs.
+#[derive(Args, Debug)]
+struct InlineArgs {
+    #[command(flatten)]
+    input: InputArgs,
 
     /// Directory to save downloaded images.
     /// If not specified, defaults to an 'images' subdirectory within the source directory.
@@ -32,13 +90,183 @@ struct CliArgs {
     #[arg(long = "base64-out", value_name = "BASE64_OUTPUT_DIR")]
     base64_output_directory: Option<PathBuf>,
 
-    /// Number of concurrent jobs to run.
-    #[arg(short, long, value_name = "NUM_JOBS", default_value_t = 4)]
-    concurrency: usize,
+    /// Write each page of a Firecrawl crawl/batch envelope (a 'data' array of pages)
+    /// to its own JSON file instead of one rewritten envelope.
+    #[arg(long)]
+    explode: bool,
 
-    /// Display a progress bar.
+    /// Number of times a download is retried after a transient failure
+    /// (timeouts, connection errors, HTTP 408/425/429/500/502/503/504).
+    #[arg(long, value_name = "NUM_RETRIES", default_value_t = 0)]
+    retries: u32,
+
+    /// Initial delay between retries in milliseconds. It doubles with every retry and is
+    /// jittered; a 'Retry-After' header from the server takes precedence.
+    #[arg(long, value_name = "MILLISECONDS", default_value_t = 500)]
+    retry_backoff: u64,
+
+    /// Maximum number of download requests per second across all jobs.
+    #[arg(long, value_name = "REQUESTS_PER_SECOND")]
+    max_rps: Option<f64>,
+
+    /// Maximum number of download requests per second to any single host.
+    #[arg(long, value_name = "REQUESTS_PER_SECOND")]
+    max_rps_per_host: Option<f64>,
+
+    /// Read screenshots from an offline mirror instead of downloading them:
+    /// 'https://host/a/b.png' is looked up as 'MIRROR_DIR/host/a/b.png'.
+    #[arg(long, value_name = "MIRROR_DIR")]
+    mirror: Option<PathBuf>,
+
+    /// Skip files whose output in the base64 directory is newer than the input.
+    /// Files whose screenshots are all data URLs already are always skipped.
     #[arg(long)]
-    progress: bool,
+    incremental: bool,
+
+    /// Name saved images by the SHA-256 of their bytes, in subdirectories of the image
+    /// directory named after its first two hex digits. Identical images are stored once.
+    #[arg(long)]
+    content_addressed: bool,
+
+    /// How saved images are named: 'url' (the last URL path segment), 'json-stem' (after the
+    /// JSON file and field), 'hash' (the SHA-256 of the image) or a template such as
+    /// '{stem}-{hash8}.{ext}', where '{name}' is the URL file name without extension and
+    /// '{sha256}' the full hash. Names are sanitized for portable filesystems.
+    #[arg(
+        long,
+        value_name = "STRATEGY",
+        default_value = "url",
+        conflicts_with = "content_addressed"
+    )]
+    naming: ImageNaming,
+
+    /// What to do when a different image already took a name during this run: 'suffix'
+    /// appends '-2', '-3', ...; 'error' leaves the field untouched; 'overwrite' replaces it.
+    #[arg(long, value_name = "POLICY", default_value = "suffix")]
+    on_collision: OnCollision,
+
+    /// Which type an image is saved as when its URL extension, Content-Type header and
+    /// content disagree: 'trust-sniffed' (the content), 'trust-header', or 'fail' to leave
+    /// the field untouched. The file extension follows the chosen type.
+    #[arg(long, value_name = "POLICY", default_value = "trust-sniffed")]
+    on_type_mismatch: OnTypeMismatch,
+
+    /// Image type to accept, as an extension such as 'png' or a MIME type such as
+    /// 'image/webp'. Can be repeated. Defaults to every image type; payloads that are not
+    /// images (e.g. the error page of an expired URL) are always rejected.
+    #[arg(long = "allow-type", value_name = "TYPE")]
+    allowed_types: Vec<String>,
+
+    /// Inline whatever the screenshot URLs return, without checking that it is an image.
+    #[arg(long, conflicts_with = "allowed_types")]
+    no_validate: bool,
+
+    /// Re-encode images as 'jpeg', 'webp' or 'avif' before inlining them, to make the data
+    /// URLs smaller. Images that would not get smaller are inlined as downloaded.
+    #[arg(long, value_name = "FORMAT")]
+    transcode: Option<TranscodeFormat>,
+
+    /// Encoder quality for '--transcode', from 1 (smallest) to 100 (best).
+    #[arg(
+        long,
+        value_name = "QUALITY",
+        default_value_t = fcjp::transcode::DEFAULT_QUALITY,
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        requires = "transcode"
+    )]
+    quality: u8,
+
+    /// Save the transcoded images to the image directory instead of the downloaded originals.
+    #[arg(long, requires = "transcode")]
+    save_transcoded: bool,
+
+    /// Scale inlined images down to at most this many pixels wide, keeping their aspect ratio.
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+    max_width: Option<u32>,
+
+    /// Scale inlined images down to at most this many pixels high, keeping their aspect ratio.
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+    max_height: Option<u32>,
+
+    /// Add a thumbnail of each screenshot to its document, e.g. a 'screenshotThumbnail' data
+    /// URL next to 'screenshot'. SIZE bounds it as WIDTH, WIDTHxHEIGHT or xHEIGHT pixels.
+    #[arg(long, value_name = "SIZE")]
+    thumbnail: Option<Resize>,
+
+    /// Format of the thumbnails: 'jpeg', 'webp' or 'avif'.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "webp",
+        requires = "thumbnail"
+    )]
+    thumbnail_format: TranscodeFormat,
+
+    /// Encoder quality for '--thumbnail', from 1 (smallest) to 100 (best).
+    #[arg(
+        long,
+        value_name = "QUALITY",
+        default_value_t = fcjp::transcode::DEFAULT_QUALITY,
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        requires = "thumbnail"
+    )]
+    thumbnail_quality: u8,
+
+    /// Inline only the top PIXELS of taller screenshots, e.g. to keep what is above the fold.
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with = "tile_height"
+    )]
+    crop_height: Option<u32>,
+
+    /// Slice each screenshot into tiles PIXELS tall, saved next to it and added to its
+    /// document as an array in '--tiles-field'.
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+    tile_height: Option<u32>,
+
+    /// Field the tiles of a screenshot are stored in, next to the screenshot field.
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = fcjp::split::DEFAULT_TILES_FIELD,
+        requires = "tile_height"
+    )]
+    tiles_field: String,
+
+    /// Rewrite fields to the saved image's path relative to the output JSON instead of
+    /// inlining a data URL.
+    #[arg(long, conflicts_with = "url_template")]
+    relative_paths: bool,
+
+    /// Rewrite fields to a URL built from this template instead of inlining a data URL.
+    /// '{filename}', '{sha256}' and '{ext}' are replaced with the image file name, the hex
+    /// SHA-256 of its bytes and its extension, e.g. 'https://cdn.example/{sha256}.{ext}'.
+    #[arg(long, value_name = "TEMPLATE")]
+    url_template: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct ExtractArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// Directory to save decoded images.
+    /// If not specified, defaults to an 'images' subdirectory within the source directory.
+    #[arg(long = "image-out", value_name = "IMAGE_OUTPUT_DIR")]
+    image_output_directory: Option<PathBuf>,
+
+    /// Directory to save the slimmed JSON files.
+    /// If not specified, defaults to an 'extracted' subdirectory within the source directory.
+    #[arg(long = "json-out", value_name = "JSON_OUTPUT_DIR")]
+    json_output_directory: Option<PathBuf>,
+
+    /// Rewrite fields to a URL built from this template instead of a relative file path.
+    /// '{filename}', '{sha256}' and '{ext}' are replaced with the image file name, the hex
+    /// SHA-256 of its bytes and its extension, e.g. 'https://cdn.example/{filename}'.
+    #[arg(long, value_name = "TEMPLATE")]
+    url_template: Option<String>,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -49,147 +277,400 @@ fn main() -> Result<(), Box<dyn Error>> {
         env!("CARGO_PKG_VERSION")
     );
 
-    if !cli_args.directory.exists() {
-        return Err(Box::new(AppError(format!(
-            "Input directory does not exist: {:?}",
-            cli_args.directory
-        ))));
+    match cli_args.command {
+        Some(Command::Extract(extract_args)) => run_extract(extract_args),
+        None => run_inline(cli_args.inline),
     }
-    if !cli_args.directory.is_dir() {
-        return Err(Box::new(AppError(format!(
-            "Input path is not a directory: {:?}",
-            cli_args.directory
-        ))));
-    }
-    let canonical_input_path = fs::canonicalize(&cli_args.directory)?;
-    println!("Input directory for JSON files: {:?}", canonical_input_path);
+}
 
-    let image_dir_path = cli_args
-        .image_output_directory
-        .unwrap_or_else(|| canonical_input_path.join(IMAGE_DIR_NAME));
-    let base64_dir_path = cli_args
-        .base64_output_directory
-        .unwrap_or_else(|| canonical_input_path.join(BASE64_DIR_NAME));
+fn run_inline(args: InlineArgs) -> Result<(), Box<dyn Error>> {
+    let canonical_input_path = prepare_input(&args.input)?;
 
-    fs::create_dir_all(&image_dir_path)?;
-    println!(
-        "Image output directory: {:?}",
-        fs::canonicalize(&image_dir_path)?
-    );
-    fs::create_dir_all(&base64_dir_path)?;
-    println!(
-        "Base64 JSON output directory: {:?}",
-        fs::canonicalize(&base64_dir_path)?
-    );
+    let image_dir_path = prepare_output_dir(
+        args.image_output_directory,
+        &canonical_input_path,
+        IMAGE_DIR_NAME,
+    )?;
+    println!("Image output directory: {:?}", image_dir_path);
+    let base64_dir_path = prepare_output_dir(
+        args.base64_output_directory,
+        &canonical_input_path,
+        BASE64_DIR_NAME,
+    )?;
+    println!("Base64 JSON output directory: {:?}", base64_dir_path);
+
+    let reporter = BatchReporter::new(args.input.progress)?;
+    let mut config = Processor::builder(&image_dir_path, &base64_dir_path)
+        .include(args.input.include.clone())
+        .exclude(args.input.exclude.clone())
+        .explode_envelopes(args.explode)
+        .retry(RetryPolicy {
+            max_retries: args.retries,
+            initial_backoff: Duration::from_millis(args.retry_backoff),
+            ..Default::default()
+        })
+        .rate_limiter(Arc::new(RateLimiter::new(
+            args.max_rps,
+            args.max_rps_per_host,
+        )))
+        .incremental(args.incremental)
+        .concurrency(args.input.concurrency)
+        .content_addressed(args.content_addressed)
+        .naming(args.naming)
+        .on_collision(args.on_collision)
+        .on_type_mismatch(args.on_type_mismatch)
+        .transcode(args.transcode.map(|format| Transcode {
+            format,
+            quality: args.quality,
+            keep_original: !args.save_transcoded,
+        }))
+        .resize(
+            (args.max_width.is_some() || args.max_height.is_some()).then_some(Resize {
+                max_width: args.max_width,
+                max_height: args.max_height,
+            }),
+        )
+        .thumbnail(args.thumbnail.map(|size| Thumbnail {
+            size,
+            format: args.thumbnail_format,
+            quality: args.thumbnail_quality,
+        }))
+        .split(match (args.crop_height, args.tile_height) {
+            (Some(height), _) => Some(Split::Crop { height }),
+            (None, Some(height)) => Some(Split::Tiles {
+                height,
+                field: args.tiles_field.clone(),
+            }),
+            (None, None) => None,
+        })
+        .validation((!args.no_validate).then(|| ImageValidation {
+            allowed_types: args.allowed_types.clone(),
+            ..Default::default()
+        }))
+        .on_event(reporter.log_events())
+        .on_file_result(reporter.report_results());
+    if !args.input.fields.is_empty() {
+        config = config.fields(args.input.fields.clone());
+    }
+    if args.relative_paths {
+        config = config.target(ScreenshotTarget::RelativePath);
+    }
+    if let Some(template) = args.url_template {
+        config = config.target(ScreenshotTarget::UrlTemplate(template));
+    }
+    if let Some(mirror_dir) = &args.mirror {
+        println!("Reading screenshots from mirror: {:?}", mirror_dir);
+        config = config.fetcher(Arc::new(MirrorFetcher::new(mirror_dir)));
+    }
+    let processor = config.build()?;
 
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(cli_args.concurrency)
-        .build_global()?;
-    println!("Using {} concurrent jobs.", cli_args.concurrency);
+    println!("Using {} concurrent jobs.", args.input.concurrency);
     println!();
+    let results = processor.process_dir(&canonical_input_path)?;
 
-    let json_files_to_process: Vec<PathBuf> = fs::read_dir(&canonical_input_path)?
-        .filter_map(Result::ok)
-        .map(|entry| entry.path())
-        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"))
-        .collect();
+    // Files in which no image could be inlined because of rejections are not written, and
+    // count as failed
+    let rejected_images: usize = results
+        .iter()
+        .map(|(_, result)| match result {
+            FileProcessResult::Success(reports) | FileProcessResult::Rejected(_, reports) => {
+                reports
+                    .iter()
+                    .filter(|report| matches!(report.status, FieldStatus::Rejected(_)))
+                    .count()
+            }
+            _ => 0,
+        })
+        .sum();
+    let mut summary = vec![format!("Rejected images:         {}", rejected_images)];
+    if args.content_addressed {
+        let deduplication = processor.deduplication();
+        summary.push(format!(
+            "Deduplicated images:     {} ({} bytes)",
+            deduplication.images, deduplication.bytes
+        ));
+    }
+    reporter.finish(&canonical_input_path, &results, summary)
+}
 
-    if json_files_to_process.is_empty() {
-        println!(
-            "No .json files found in the input directory: {:?}",
-            canonical_input_path
-        );
-        return Ok(());
+fn run_extract(args: ExtractArgs) -> Result<(), Box<dyn Error>> {
+    let canonical_input_path = prepare_input(&args.input)?;
+    let reporter = BatchReporter::new(args.input.progress)?;
+    let mut extract_options = ExtractOptions {
+        include: args.input.include.clone(),
+        exclude: args.input.exclude.clone(),
+        concurrency: args.input.concurrency,
+        on_file_result: reporter.report_results(),
+        ..Default::default()
+    };
+    if !args.input.fields.is_empty() {
+        extract_options.screenshot_pointers = args.input.fields.clone();
+    }
+    if let Some(template) = args.url_template {
+        extract_options.target = ExtractTarget::UrlTemplate(template);
     }
 
-    let total_files_found = json_files_to_process.len();
-    println!("Found {} JSON file(s) to process.", total_files_found);
+    let image_dir_path = prepare_output_dir(
+        args.image_output_directory,
+        &canonical_input_path,
+        IMAGE_DIR_NAME,
+    )?;
+    println!("Image output directory: {:?}", image_dir_path);
+    let json_dir_path = prepare_output_dir(
+        args.json_output_directory,
+        &canonical_input_path,
+        EXTRACTED_DIR_NAME,
+    )?;
+    println!("Extracted JSON output directory: {:?}", json_dir_path);
+
+    println!("Using {} concurrent jobs.", args.input.concurrency);
+    println!();
+    let results = extract_dir(
+        &canonical_input_path,
+        &image_dir_path,
+        &json_dir_path,
+        &extract_options,
+        reporter.log_events().as_ref(),
+    )?;
+    reporter.finish(&canonical_input_path, &results, Vec::new())
+}
 
-    let pb_option = if cli_args.progress {
-        let bar = ProgressBar::new(total_files_found as u64);
-        bar.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) {msg}")?
-            .progress_chars("=>-")); // Changed progress chars for variety
-        Some(bar)
-    } else {
-        None
+/// Validates the shared input arguments and returns the canonical input directory.
+fn prepare_input(input: &InputArgs) -> Result<PathBuf, Box<dyn Error>> {
+    let Some(directory) = &input.directory else {
+        return Err(Box::new(AppError::InvalidInput(
+            "No input directory given".to_string(),
+        )));
     };
+    if !directory.exists() {
+        return Err(Box::new(AppError::InvalidInput(format!(
+            "Input directory does not exist: {:?}",
+            directory
+        ))));
+    }
+    if !directory.is_dir() {
+        return Err(Box::new(AppError::InvalidInput(format!(
+            "Input path is not a directory: {:?}",
+            directory
+        ))));
+    }
+    for pointer in &input.fields {
+        validate_json_pointer(pointer)?;
+    }
 
-    let http_client = Arc::new(
-        reqwest::blocking::Client::builder()
-            .user_agent(format!("ScreenshotProcessor/{}", env!("CARGO_PKG_VERSION")))
-            .timeout(std::time::Duration::from_secs(60))
-            .build()?,
-    );
+    let canonical_input_path = fs::canonicalize(directory)?;
+    println!("Input directory for JSON files: {:?}", canonical_input_path);
+    Ok(canonical_input_path)
+}
 
-    let processed_successfully = AtomicUsize::new(0);
-    let skipped_files = AtomicUsize::new(0);
-    let failed_to_process = AtomicUsize::new(0);
-
-    let show_ind_progress = cli_args.progress; // Capture this for the closure
-
-    json_files_to_process
-        .par_iter()
-        .progress_with(pb_option.clone().unwrap_or_else(ProgressBar::hidden))
-        .for_each(|json_path| {
-            let client_clone = Arc::clone(&http_client);
-            let result = process_json_file(
-                json_path,
-                &image_dir_path,
-                &base64_dir_path,
-                &client_clone,
-                show_ind_progress,
-            );
-            match result {
-                FileProcessResult::Success => {
-                    processed_successfully.fetch_add(1, Ordering::SeqCst);
+/// Creates an output directory (defaulting to `default_name` inside the input directory)
+/// and returns its canonical path.
+fn prepare_output_dir(
+    requested: Option<PathBuf>,
+    canonical_input_path: &Path,
+    default_name: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let dir = requested.unwrap_or_else(|| canonical_input_path.join(default_name));
+    fs::create_dir_all(&dir)?;
+    Ok(fs::canonicalize(&dir)?)
+}
+
+/// Reports the files of a batch as the library processes them: per-file log lines, or a
+/// progress bar with only skips, warnings and failures printed above it. Prints the summary
+/// once the batch is done.
+struct BatchReporter {
+    progress_bar: Option<ProgressBar>,
+}
+
+impl BatchReporter {
+    fn new(progress: bool) -> Result<Arc<Self>, Box<dyn Error>> {
+        let progress_bar = if progress {
+            // The length is set once the input directory has been walked
+            let bar = ProgressBar::new(0);
+            bar.set_style(ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) {msg}")?
+                .progress_chars("=>-")); // Changed progress chars for variety
+            Some(bar)
+        } else {
+            None
+        };
+        Ok(Arc::new(BatchReporter { progress_bar }))
+    }
+
+    /// Prints a message to stderr, above the progress bar if one is shown.
+    fn print_message(&self, message: String) {
+        match &self.progress_bar {
+            Some(pb) => pb.println(message),
+            None => eprintln!("{}", message),
+        }
+    }
+
+    /// Renders the library's progress events as per-file log lines. They are left out while
+    /// a progress bar is shown; skips, warnings and failures are reported from the file
+    /// results.
+    fn log_events(self: &Arc<Self>) -> Arc<EventHandler> {
+        let reporter = Arc::clone(self);
+        Arc::new(move |event: &ProgressEvent| {
+            if let ProgressEvent::Found { files, .. } = event {
+                if *files > 0 {
+                    println!("Found {} JSON file(s) to process.", files);
+                }
+                if let Some(pb) = &reporter.progress_bar {
+                    pb.set_length(*files as u64);
                 }
-                FileProcessResult::Skipped(reason) => {
-                    skipped_files.fetch_add(1, Ordering::SeqCst);
-                    if !show_ind_progress {
-                        eprintln!("[SKIP] {}", reason);
-                    } else if let Some(pb) = &pb_option {
-                        pb.println(format!("[SKIP] {}", reason)); // Print skip message above progress bar
-                    }
+                return;
+            }
+            if reporter.progress_bar.is_some() {
+                return;
+            }
+            match event {
+                ProgressEvent::Started { file } => {
+                    let file_name = file.file_name().unwrap_or(file.as_os_str());
+                    println!("Processing file: {}", file_name.to_string_lossy());
+                }
+                ProgressEvent::Downloading { url, attempt, .. } if *attempt > 1 => {
+                    println!("  Retrying {} (attempt {})", url, attempt);
                 }
-                FileProcessResult::Failed(file_name, error_msg) => {
-                    failed_to_process.fetch_add(1, Ordering::SeqCst);
-                    if let Some(pb) = &pb_option {
-                        pb.println(format!("[ERROR] File '{}': {}", file_name, error_msg)); // Print error above progress bar
-                    } else {
-                        eprintln!("[ERROR] File '{}': {}", file_name, error_msg);
-                    }
+                ProgressEvent::Downloaded { pointer, bytes, .. } => {
+                    println!("  Downloaded {} ({} bytes)", pointer, bytes);
                 }
+                ProgressEvent::Saved {
+                    kind: OutputKind::Image,
+                    path,
+                    ..
+                } => println!("  Image saved to: {:?}", path),
+                ProgressEvent::Saved {
+                    kind: OutputKind::Json,
+                    path,
+                    ..
+                } => println!("  JSON saved to: {:?}", path),
+                ProgressEvent::Renamed {
+                    requested, path, ..
+                } => println!("  {:?} is taken, saving as {:?}", requested, path),
+                ProgressEvent::Deduplicated { path, .. } => {
+                    println!("  Image already stored: {:?}", path)
+                }
+                _ => {}
             }
-        });
+        })
+    }
 
-    if let Some(bar) = pb_option {
-        bar.finish_with_message("All files processed.");
+    /// Reports every file result as soon as the library hands it over.
+    fn report_results(self: &Arc<Self>) -> Arc<FileResultHandler> {
+        let reporter = Arc::clone(self);
+        Arc::new(move |json_path: &Path, result: &FileProcessResult| {
+            reporter.report(json_path, result)
+        })
     }
 
-    println!("----------------------------------------");
-    println!("Processing Summary:");
-    println!("Total JSON files found:    {}", total_files_found);
-    println!(
-        "Processed successfully:    {}",
-        processed_successfully.load(Ordering::SeqCst)
-    );
-    println!(
-        "Skipped (e.g., no URL):  {}",
-        skipped_files.load(Ordering::SeqCst)
-    );
-    println!(
-        "Failed to process:       {}",
-        failed_to_process.load(Ordering::SeqCst)
-    );
-    println!("----------------------------------------");
+    fn report(&self, json_path: &Path, result: &FileProcessResult) {
+        match result {
+            // Some fields of a multi-field file may still have failed
+            FileProcessResult::Success(reports) => self.report_fields(json_path, reports),
+            FileProcessResult::Skipped(reason) => {
+                self.print_message(format!("[SKIP] {}", reason));
+            }
+            FileProcessResult::UpToDate(reason) => {
+                if self.progress_bar.is_none() {
+                    eprintln!("[UP-TO-DATE] {}", reason);
+                }
+            }
+            FileProcessResult::Rejected(file_name, reports) => {
+                self.report_fields(json_path, reports);
+                self.print_message(format!(
+                    "[ERROR] File '{}': no screenshot could be inlined, so it was not written",
+                    file_name
+                ));
+            }
+            FileProcessResult::Failed(file_name, error_msg) => {
+                self.print_message(format!("[ERROR] File '{}': {}", file_name, error_msg));
+            }
+        }
+        if let Some(pb) = &self.progress_bar {
+            pb.inc(1);
+        }
+    }
 
-    if failed_to_process.load(Ordering::SeqCst) > 0 {
-        return Err(Box::new(AppError(format!(
-            "{} files failed to process.",
-            failed_to_process.load(Ordering::SeqCst)
-        ))));
+    /// Prints the fields of a file that were left untouched.
+    fn report_fields(&self, json_path: &Path, reports: &[FieldReport]) {
+        for report in reports {
+            let (tag, error) = match &report.status {
+                FieldStatus::Failed(error) => ("WARN", error),
+                FieldStatus::Rejected(error) => ("REJECTED", error),
+                _ => continue,
+            };
+            let location = match report.line {
+                Some(line) => format!("line {}, field '{}'", line, report.pointer),
+                None => format!("field '{}'", report.pointer),
+            };
+            let mut warn_msg = format!(
+                "[{}] File '{}', {}: {}",
+                tag,
+                json_path.display(),
+                location,
+                error
+            );
+            if report.attempts > 1 {
+                warn_msg.push_str(&format!(" (after {} attempts)", report.attempts));
+            }
+            self.print_message(warn_msg);
+        }
     }
 
-    Ok(())
+    /// Prints the summary of the batch, with any mode-specific `extra_summary` lines, and
+    /// fails if any file failed.
+    fn finish(
+        &self,
+        canonical_input_path: &Path,
+        results: &[(PathBuf, FileProcessResult)],
+        extra_summary: Vec<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        if results.is_empty() {
+            println!(
+                "No matching JSON files found in the input directory: {:?}",
+                canonical_input_path
+            );
+            return Ok(());
+        }
+        if let Some(bar) = &self.progress_bar {
+            bar.finish_with_message("All files processed.");
+        }
+
+        let count = |matches: fn(&FileProcessResult) -> bool| {
+            results.iter().filter(|(_, result)| matches(result)).count()
+        };
+        let failed_to_process = count(|result| {
+            matches!(
+                result,
+                FileProcessResult::Failed(..) | FileProcessResult::Rejected(..)
+            )
+        });
+
+        println!("----------------------------------------");
+        println!("Processing Summary:");
+        println!("Total JSON files found:    {}", results.len());
+        println!(
+            "Processed successfully:    {}",
+            count(|result| matches!(result, FileProcessResult::Success(_)))
+        );
+        println!(
+            "Skipped (e.g., no URL):  {}",
+            count(|result| matches!(result, FileProcessResult::Skipped(_)))
+        );
+        println!(
+            "Up to date:              {}",
+            count(|result| matches!(result, FileProcessResult::UpToDate(_)))
+        );
+        println!("Failed to process:       {}", failed_to_process);
+        for line in extra_summary {
+            println!("{}", line);
+        }
+        println!("----------------------------------------");
+
+        if failed_to_process > 0 {
+            return Err(format!("{} files failed to process.", failed_to_process).into());
+        }
+
+        Ok(())
+    }
 }
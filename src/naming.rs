@@ -0,0 +1,375 @@
+use percent_encoding::percent_decode_str;
+use std::{path::Path, str::FromStr};
+use url::Url;
+
+use crate::{
+    AppError, derived_image_stem,
+    extract::{EXTENSION_PLACEHOLDER, SHA256_PLACEHOLDER, extension_for_mime, mime_for_extension},
+};
+
+/// Placeholder in a naming template that is replaced with the name derived from the JSON
+/// file, line and field (e.g. `crawl-data-0-screenshot`), without extension.
+pub const STEM_PLACEHOLDER: &str = "{stem}";
+/// Placeholder in a naming template that is replaced with the last URL path segment without
+/// its extension, or with the [`STEM_PLACEHOLDER`] value when the URL has none.
+pub const URL_NAME_PLACEHOLDER: &str = "{name}";
+/// Placeholder in a naming template that is replaced with the first eight hex digits of the
+/// SHA-256 of the image bytes.
+pub const HASH8_PLACEHOLDER: &str = "{hash8}";
+
+/// Extension used when neither the image type nor the URL provides one.
+const DEFAULT_EXTENSION: &str = "bin";
+
+/// MIME type of images whose type could not be determined.
+pub(crate) const UNKNOWN_MIME_TYPE: &str = "application/octet-stream";
+
+/// Longest file name [`sanitize_filename`] produces, in bytes. Most filesystems allow 255;
+/// the margin leaves room for collision suffixes.
+const MAX_FILENAME_BYTES: usize = 200;
+
+/// Names Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// --- Naming strategies ---
+/// How saved images are named. The extension follows the image type (see [`OnTypeMismatch`]);
+/// an extension in the URL is kept if it names the same type. Every name is passed through
+/// [`sanitize_filename`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ImageNaming {
+    /// The last URL path segment, percent-decoded (`screenshot.png`). URLs without one, and
+    /// entries of URL arrays, fall back to [`ImageNaming::JsonStem`].
+    #[default]
+    Url,
+    /// A name derived from the JSON file stem, plus the line and field where they are
+    /// needed to tell images apart: `page.png`, `pages-line3.png`, `crawl-data-0-screenshot.png`.
+    JsonStem,
+    /// The hex SHA-256 of the image bytes, e.g. `9f86d0…08.png`.
+    Hash,
+    /// A template such as `{stem}-{hash8}.{ext}`; see [`STEM_PLACEHOLDER`] and the other
+    /// placeholders next to it. [`SHA256_PLACEHOLDER`] and [`EXTENSION_PLACEHOLDER`] are
+    /// supported as well.
+    Template(String),
+}
+
+impl ImageNaming {
+    /// Whether names depend on the image bytes, which then have to be hashed.
+    pub(crate) fn uses_hash(&self) -> bool {
+        match self {
+            ImageNaming::Hash => true,
+            ImageNaming::Template(template) => {
+                template.contains(HASH8_PLACEHOLDER) || template.contains(SHA256_PLACEHOLDER)
+            }
+            ImageNaming::Url | ImageNaming::JsonStem => false,
+        }
+    }
+
+    /// Names the image of type `mime_type` fetched from `origin`. `sha256` is the hex
+    /// SHA-256 of the image bytes; it is only used when [`ImageNaming::uses_hash`] says so.
+    pub(crate) fn file_name(
+        &self,
+        origin: &ImageOrigin<'_>,
+        mime_type: &str,
+        sha256: Option<&str>,
+    ) -> Result<String, AppError> {
+        let url_name = url_file_name(origin.url);
+        let (url_stem, url_extension) = match &url_name {
+            Some(name) => {
+                let (stem, extension) = split_image_extension(name);
+                (Some(stem), extension)
+            }
+            None => (None, None),
+        };
+        let extension = match (url_extension, extension_for_mime(mime_type)) {
+            // 'photo.jpeg' keeps its spelling of the extension
+            (Some(url_extension), Some(_))
+                if same_type(mime_for_extension(url_extension), Some(mime_type)) =>
+            {
+                url_extension
+            }
+            (_, Some(extension)) => extension,
+            (Some(url_extension), None) if mime_type == UNKNOWN_MIME_TYPE => url_extension,
+            _ => DEFAULT_EXTENSION,
+        };
+        let sha256 = sha256.unwrap_or_default();
+
+        let name = match self {
+            ImageNaming::Url => match url_stem {
+                Some(url_stem) if !origin.indexed => format!("{}.{}", url_stem, extension),
+                _ => format!("{}.{}", origin.derived_stem()?, extension),
+            },
+            ImageNaming::JsonStem => format!("{}.{}", origin.derived_stem()?, extension),
+            ImageNaming::Hash => format!("{}.{}", sha256, extension),
+            ImageNaming::Template(template) => {
+                let mut name = template.clone();
+                if name.contains(STEM_PLACEHOLDER) {
+                    name = name.replace(STEM_PLACEHOLDER, &origin.derived_stem()?);
+                }
+                if name.contains(URL_NAME_PLACEHOLDER) {
+                    let url_stem = match url_stem {
+                        Some(url_stem) => url_stem.to_string(),
+                        None => origin.derived_stem()?,
+                    };
+                    name = name.replace(URL_NAME_PLACEHOLDER, &url_stem);
+                }
+                name.replace(HASH8_PLACEHOLDER, &sha256[..sha256.len().min(8)])
+                    .replace(SHA256_PLACEHOLDER, sha256)
+                    .replace(EXTENSION_PLACEHOLDER, extension)
+            }
+        };
+        Ok(sanitize_filename(&name))
+    }
+}
+
+impl FromStr for ImageNaming {
+    type Err = AppError;
+
+    /// Parses `url`, `json-stem`, `hash`, or a template holding at least one placeholder.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "url" => Ok(ImageNaming::Url),
+            "json-stem" => Ok(ImageNaming::JsonStem),
+            "hash" => Ok(ImageNaming::Hash),
+            template if template.contains('{') => Ok(ImageNaming::Template(template.to_string())),
+            other => Err(AppError::InvalidInput(format!(
+                "Unknown naming strategy '{}': expected 'url', 'json-stem', 'hash' or a template such as '{{stem}}-{{hash8}}.{{ext}}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Where a fetched image comes from, for naming it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ImageOrigin<'a> {
+    pub url: &'a str,
+    /// The URL is one entry of an array; [`ImageNaming::Url`] gives it an index-based name.
+    pub indexed: bool,
+    pub json_path: &'a Path,
+    /// 1-based line number when the document comes from a JSON Lines file.
+    pub line: Option<usize>,
+    /// JSON Pointer of the field within its file.
+    pub pointer: &'a str,
+}
+
+impl ImageOrigin<'_> {
+    fn derived_stem(&self) -> Result<String, AppError> {
+        derived_image_stem(self.json_path, self.line, self.pointer)
+    }
+
+    /// Image MIME type named by the extension of the URL's file name, if any.
+    pub(crate) fn url_mime_type(&self) -> Option<&'static str> {
+        let url_name = url_file_name(self.url)?;
+        split_image_extension(&url_name)
+            .1
+            .and_then(mime_for_extension)
+    }
+}
+
+/// The last path segment of `url`, percent-decoded, if it is not empty.
+fn url_file_name(url: &str) -> Option<String> {
+    let parsed_url = Url::parse(url).ok()?;
+    let segment = parsed_url.path_segments()?.next_back()?;
+    let decoded = percent_decode_str(segment).decode_utf8_lossy();
+    Some(decoded.into_owned()).filter(|name| !name.is_empty())
+}
+
+/// Splits a file name into its stem and its extension, if that names an image type:
+/// `shot.webp` becomes `shot` and `webp`, but `report.v2` is all stem.
+fn split_image_extension(name: &str) -> (&str, Option<&str>) {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && mime_for_extension(extension).is_some() => {
+            (stem, Some(extension))
+        }
+        _ => (name, None),
+    }
+}
+
+// --- Image types ---
+/// Which type an image is saved and inlined as when the extension of its URL, the
+/// `Content-Type` the server sent and the type sniffed from its bytes disagree. Generic
+/// `Content-Type`s such as `application/octet-stream` and missing information are not
+/// counted as disagreement.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnTypeMismatch {
+    /// The type sniffed from the bytes; then the `Content-Type`, then the URL extension.
+    #[default]
+    TrustSniffed,
+    /// The `Content-Type`; then the sniffed type, then the URL extension.
+    TrustHeader,
+    /// Leave the field untouched and report [`AppError::TypeMismatch`].
+    Fail,
+}
+
+impl FromStr for OnTypeMismatch {
+    type Err = AppError;
+
+    /// Parses `trust-sniffed`, `trust-header` or `fail`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "trust-sniffed" => Ok(OnTypeMismatch::TrustSniffed),
+            "trust-header" => Ok(OnTypeMismatch::TrustHeader),
+            "fail" => Ok(OnTypeMismatch::Fail),
+            other => Err(AppError::InvalidInput(format!(
+                "Unknown type mismatch policy '{}': expected 'trust-sniffed', 'trust-header' or 'fail'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Decides the MIME type of an image fetched from `url`, from the type named by its URL
+/// extension, its `Content-Type` and its bytes, according to `on_mismatch`.
+pub(crate) fn resolve_mime_type(
+    url: &str,
+    url_type: Option<&str>,
+    content_type: Option<&str>,
+    bytes: &[u8],
+    on_mismatch: OnTypeMismatch,
+) -> Result<String, AppError> {
+    let header_type = content_type.map(mime_essence).filter(|mime_type| {
+        !mime_type.is_empty()
+            && mime_type != UNKNOWN_MIME_TYPE
+            && mime_type != "binary/octet-stream"
+    });
+    let header_type = header_type.as_deref();
+    let sniffed_type = infer::get(bytes).map(|kind| kind.mime_type());
+
+    let candidates = match on_mismatch {
+        OnTypeMismatch::TrustSniffed | OnTypeMismatch::Fail => {
+            [sniffed_type, header_type, url_type]
+        }
+        OnTypeMismatch::TrustHeader => [header_type, sniffed_type, url_type],
+    };
+    let mut known = candidates.into_iter().flatten();
+    let mime_type = known.next().unwrap_or(UNKNOWN_MIME_TYPE);
+    if on_mismatch == OnTypeMismatch::Fail
+        && known.any(|other| !same_type(Some(other), Some(mime_type)))
+    {
+        return Err(AppError::TypeMismatch {
+            url: url.to_string(),
+            url_type: url_type.map(String::from),
+            content_type: header_type.map(String::from),
+            sniffed_type: sniffed_type.map(String::from),
+        });
+    }
+    Ok(mime_type.to_string())
+}
+
+/// `image/png` for `Image/PNG; charset=binary`.
+fn mime_essence(content_type: &str) -> String {
+    let essence = content_type.split(';').next().unwrap_or_default();
+    essence.trim().to_ascii_lowercase()
+}
+
+/// Whether two MIME types name the same image type, allowing for aliases such as
+/// `image/jpg`.
+fn same_type(a: Option<&str>, b: Option<&str>) -> bool {
+    let canonical = |mime_type: Option<&str>| {
+        mime_type
+            .and_then(extension_for_mime)
+            .and_then(mime_for_extension)
+            .map(String::from)
+            .or_else(|| mime_type.map(mime_essence))
+    };
+    canonical(a) == canonical(b)
+}
+
+// --- Sanitization ---
+/// Makes `name` safe to use as a file name on Linux, macOS and Windows alike: path
+/// separators, characters Windows forbids and control characters become `_`, leading dots
+/// and trailing dots and spaces are dropped, device names such as `CON` get a `_` prefix,
+/// and long names are shortened to 200 bytes, keeping the extension. An empty result
+/// becomes `image`.
+pub fn sanitize_filename(name: &str) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = replaced
+        .trim_start_matches(['.', ' '])
+        .trim_end_matches(['.', ' ']);
+    if trimmed.is_empty() {
+        return "image".to_string();
+    }
+
+    let (stem, extension) = match trimmed.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && extension.len() <= 16 => {
+            (stem, Some(extension))
+        }
+        _ => (trimmed, None),
+    };
+    let device = stem.split('.').next().unwrap_or(stem);
+    let prefix = if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(device))
+    {
+        "_"
+    } else {
+        ""
+    };
+    let extension_len = extension.map_or(0, |extension| extension.len() + 1);
+    let max_stem_len = MAX_FILENAME_BYTES - extension_len - prefix.len();
+    let stem = if stem.len() > max_stem_len {
+        let mut stem_len = max_stem_len;
+        while !stem.is_char_boundary(stem_len) {
+            stem_len -= 1;
+        }
+        // Shortening may leave a trailing space or dot in front of the extension
+        stem[..stem_len].trim_end_matches(['.', ' '])
+    } else {
+        stem
+    };
+    match extension {
+        Some(extension) => format!("{}{}.{}", prefix, stem, extension),
+        None => format!("{}{}", prefix, stem),
+    }
+}
+
+// --- Collisions ---
+/// What happens when an image gets the name of a different image saved earlier in the same
+/// run, e.g. two pages whose screenshot URLs both end in `screenshot.png`. Saving the same
+/// URL twice is not a collision.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnCollision {
+    /// Append `-2`, `-3`, … to the file stem until the name is free.
+    #[default]
+    Suffix,
+    /// Leave the field untouched and report [`AppError::NameCollision`].
+    Error,
+    /// Replace the earlier image; fields already pointing at it now show the new one.
+    Overwrite,
+}
+
+impl FromStr for OnCollision {
+    type Err = AppError;
+
+    /// Parses `suffix`, `error` or `overwrite`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "suffix" => Ok(OnCollision::Suffix),
+            "error" => Ok(OnCollision::Error),
+            "overwrite" => Ok(OnCollision::Overwrite),
+            other => Err(AppError::InvalidInput(format!(
+                "Unknown collision policy '{}': expected 'suffix', 'error' or 'overwrite'",
+                other
+            ))),
+        }
+    }
+}
+
+/// `screenshot-2.png` for `screenshot.png` and `number` 2.
+pub(crate) fn suffixed_filename(file_name: &str, number: u32) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}-{}.{}", stem, number, extension)
+        }
+        _ => format!("{}-{}", file_name, number),
+    }
+}
@@ -0,0 +1,950 @@
+use reqwest::Client;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::sync::Semaphore;
+
+use crate::{
+    AppError, FieldReport, FieldStatus, FileProcessResult, InputFilter, RateLimiter, RetryPolicy,
+    data_url::DataUrls,
+    document::{DirJob, DocumentRewriter, FileJob, rewrite_dir, rewrite_file, rewrite_value},
+    events::{EventHandler, FileResultHandler, ProgressEvent, ignore_events, ignore_file_results},
+    extract::{
+        expand_url_template, extension_for_mime, image_extension, relative_path, sha256_hex,
+    },
+    fetch::{HttpFetcher, ScreenshotFetcher},
+    inline::{
+        DEFAULT_DOWNLOAD_CONCURRENCY, DocumentSource, InlineOptions, InlineOutcome, InlinedImage,
+        inline_value_at,
+    },
+    naming::{ImageNaming, OnCollision, OnTypeMismatch, suffixed_filename},
+    resize::{Resize, Thumbnail},
+    runtime::{self, run_blocking},
+    sink::{FsSink, OutputKind, OutputSink},
+    split::{Split, Tiles, render_tiles, tile_filename},
+    transcode::{Transcode, reencode},
+    validate::ImageValidation,
+};
+
+// --- Configuration ---
+/// What a screenshot field is rewritten to once its image has been saved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ScreenshotTarget {
+    /// A `data:` URL holding the image itself.
+    #[default]
+    DataUrl,
+    /// The image path relative to the directory of the output JSON file, using `/` separators.
+    RelativePath,
+    /// A URL built from a template such as `https://cdn.example/{sha256}.{ext}`, see
+    /// [`crate::extract::SHA256_PLACEHOLDER`] and the other placeholders next to it.
+    UrlTemplate(String),
+}
+
+/// Settings for a [`Processor`], built up with chained setters and turned into a
+/// processor with [`ProcessorConfig::build`].
+///
+/// ```no_run
+/// use fcjp::Processor;
+///
+/// let processor = Processor::builder("out/images", "out/base64")
+///     .fields(["/screenshot", "/data/screenshot"])
+///     .explode_envelopes(true)
+///     .build()?;
+/// let results = processor.process_dir("crawls".as_ref())?;
+/// // Or, from async code: processor.process_dir_async("crawls".as_ref()).await?
+/// # Ok::<(), fcjp::AppError>(())
+/// ```
+#[derive(Clone)]
+pub struct ProcessorConfig {
+    image_dir: PathBuf,
+    output_dir: PathBuf,
+    fetcher: Option<Arc<dyn ScreenshotFetcher>>,
+    output_sink: Arc<dyn OutputSink>,
+    inline: InlineOptions,
+    target: ScreenshotTarget,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    explode_envelopes: bool,
+    incremental: bool,
+    content_addressed: bool,
+    on_collision: OnCollision,
+    transcode: Option<Transcode>,
+    resize: Option<Resize>,
+    thumbnail: Option<Thumbnail>,
+    split: Option<Split>,
+    concurrency: usize,
+    events: Arc<EventHandler>,
+    on_file_result: Arc<FileResultHandler>,
+}
+
+impl ProcessorConfig {
+    /// Starts a configuration that saves downloaded images to `image_dir` and the rewritten
+    /// JSON files to `output_dir`.
+    pub fn new(image_dir: impl Into<PathBuf>, output_dir: impl Into<PathBuf>) -> Self {
+        ProcessorConfig {
+            image_dir: image_dir.into(),
+            output_dir: output_dir.into(),
+            fetcher: None,
+            output_sink: Arc::new(FsSink),
+            inline: InlineOptions::default(),
+            target: ScreenshotTarget::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            explode_envelopes: false,
+            incremental: false,
+            content_addressed: false,
+            on_collision: OnCollision::default(),
+            transcode: None,
+            resize: None,
+            thumbnail: None,
+            split: None,
+            concurrency: DEFAULT_DOWNLOAD_CONCURRENCY,
+            events: Arc::new(ignore_events),
+            on_file_result: Arc::new(ignore_file_results),
+        }
+    }
+
+    /// HTTP client used for downloads. Defaults to a client with a 60 second timeout.
+    /// Shorthand for [`ProcessorConfig::fetcher`] with an [`HttpFetcher`].
+    pub fn http_client(mut self, http_client: Client) -> Self {
+        self.fetcher = Some(Arc::new(HttpFetcher::new(http_client)));
+        self
+    }
+
+    /// Where screenshots are fetched from, e.g. a [`crate::FileFetcher`], a
+    /// [`crate::MirrorFetcher`] or a cache of your own. Defaults to
+    /// [`HttpFetcher::with_defaults`].
+    pub fn fetcher(mut self, fetcher: Arc<dyn ScreenshotFetcher>) -> Self {
+        self.fetcher = Some(fetcher);
+        self
+    }
+
+    /// Where images and rewritten JSON files are written, e.g. a [`crate::MemorySink`],
+    /// a [`crate::TarSink`] or a closure. Outputs are still addressed by their path below
+    /// the image and output directories. Defaults to [`FsSink`].
+    pub fn output_sink(mut self, output_sink: Arc<dyn OutputSink>) -> Self {
+        self.output_sink = output_sink;
+        self
+    }
+
+    /// JSON Pointers (RFC 6901) of the fields holding screenshot URLs. Every field that
+    /// resolves to a URL is downloaded and replaced in place with a data URL; a field that
+    /// resolves to an array has each of its URL entries inlined.
+    /// Defaults to [`crate::default_screenshot_pointers`].
+    pub fn fields<I, S>(mut self, pointers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.inline.screenshot_pointers = pointers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// What the fields are rewritten to. Defaults to [`ScreenshotTarget::DataUrl`]; the other
+    /// targets keep the JSON small and leave the image next to it, or on a server of yours.
+    pub fn target(mut self, target: ScreenshotTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// How saved images are named. Defaults to [`ImageNaming::Url`]; ignored when the
+    /// processor is [content-addressed](ProcessorConfig::content_addressed).
+    pub fn naming(mut self, naming: ImageNaming) -> Self {
+        self.inline.naming = naming;
+        self
+    }
+
+    /// Which type an image is saved and inlined as when its URL extension, `Content-Type`
+    /// and content disagree. Defaults to [`OnTypeMismatch::TrustSniffed`].
+    pub fn on_type_mismatch(mut self, on_type_mismatch: OnTypeMismatch) -> Self {
+        self.inline.on_type_mismatch = on_type_mismatch;
+        self
+    }
+
+    /// Checks that downloaded payloads are images before they are saved and inlined; `None`
+    /// accepts anything. Defaults to [`ImageValidation::default`], which accepts every image
+    /// type of sane dimensions.
+    pub fn validation(mut self, validation: Option<ImageValidation>) -> Self {
+        self.inline.validation = validation;
+        self
+    }
+
+    /// Re-encodes images before they are inlined, e.g. to WebP, to make the data URLs
+    /// smaller. Defaults to `None`, which inlines images as they were downloaded.
+    pub fn transcode(mut self, transcode: Option<Transcode>) -> Self {
+        self.transcode = transcode;
+        self
+    }
+
+    /// Scales images down to fit within these bounds before they are inlined, keeping their
+    /// aspect ratio. Like transcoded images, resized ones are only saved to the image
+    /// directory if [`Transcode::keep_original`] is false. Defaults to `None`.
+    pub fn resize(mut self, resize: Option<Resize>) -> Self {
+        self.resize = resize;
+        self
+    }
+
+    /// Adds a thumbnail of each inlined screenshot to its document, next to the screenshot
+    /// field. Defaults to `None`.
+    pub fn thumbnail(mut self, thumbnail: Option<Thumbnail>) -> Self {
+        self.thumbnail = thumbnail;
+        self
+    }
+
+    /// Crops tall screenshots, or slices them into tiles. Defaults to `None`, which inlines
+    /// them whole.
+    pub fn split(mut self, split: Option<Split>) -> Self {
+        self.split = split;
+        self
+    }
+
+    /// What happens when an image gets the name of a different image saved before.
+    /// Defaults to [`OnCollision::Suffix`].
+    pub fn on_collision(mut self, on_collision: OnCollision) -> Self {
+        self.on_collision = on_collision;
+        self
+    }
+
+    /// Glob patterns of the files [`Processor::process_dir`] picks up, relative to the
+    /// input directory. Defaults to [`crate::discovery::DEFAULT_INCLUDE_GLOB`].
+    pub fn include(mut self, patterns: Vec<String>) -> Self {
+        self.include = patterns;
+        self
+    }
+
+    /// Glob patterns of the files [`Processor::process_dir`] leaves out.
+    pub fn exclude(mut self, patterns: Vec<String>) -> Self {
+        self.exclude = patterns;
+        self
+    }
+
+    /// Write each page of a crawl/batch envelope to its own JSON file instead of
+    /// writing back the rewritten envelope.
+    pub fn explode_envelopes(mut self, explode: bool) -> Self {
+        self.explode_envelopes = explode;
+        self
+    }
+
+    /// How failed screenshot downloads are retried. Defaults to no retries.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.inline.retry = retry;
+        self
+    }
+
+    /// Requests-per-second limits applied to every download attempt, retries included.
+    /// Pass the same limiter to several processors to throttle them together.
+    pub fn rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.inline.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Skip files whose output already exists and is newer than the input.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Name saved images by the SHA-256 of their bytes, sharded by its first two hex digits
+    /// (`images/9f/9f86d0…08.png`), and store identical images only once. Images are no
+    /// longer named after their URL, so [`Processor::process_dir`] keeps them all below the
+    /// image directory instead of mirroring the input layout. See
+    /// [`Processor::deduplication`] for what was saved.
+    pub fn content_addressed(mut self, content_addressed: bool) -> Self {
+        self.content_addressed = content_addressed;
+        self
+    }
+
+    /// Maximum number of screenshots downloaded at once, across all documents and files the
+    /// processor works on. [`Processor::process_dir`] also processes at most this many files
+    /// at once. Defaults to [`DEFAULT_DOWNLOAD_CONCURRENCY`]; values below 1 are raised to 1.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self.inline.download_permits = Arc::new(Semaphore::new(self.concurrency));
+        self
+    }
+
+    /// Receives a [`ProgressEvent`] for every step of the work. Defaults to ignoring them.
+    pub fn on_event(mut self, events: Arc<EventHandler>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Receives the result of every file [`Processor::process_dir`] processes as soon as it
+    /// is ready, e.g. to report progress per file. Defaults to ignoring them.
+    pub fn on_file_result(mut self, on_file_result: Arc<FileResultHandler>) -> Self {
+        self.on_file_result = on_file_result;
+        self
+    }
+
+    /// Validates the configuration and creates the processor.
+    pub fn build(self) -> Result<Processor, AppError> {
+        for pointer in &self.inline.screenshot_pointers {
+            crate::validate_json_pointer(pointer)?;
+        }
+        if let Some(split) = &self.split {
+            split.validate()?;
+        }
+        let input_filter = InputFilter::new(&self.include, &self.exclude)?;
+        let fetcher: Arc<dyn ScreenshotFetcher> = match &self.fetcher {
+            Some(fetcher) => Arc::clone(fetcher),
+            None => Arc::new(HttpFetcher::with_defaults()?),
+        };
+        Ok(Processor {
+            config: self,
+            fetcher,
+            input_filter,
+            saved_images: Mutex::new(HashMap::new()),
+            stored_images: Mutex::new(HashMap::new()),
+            deduplicated_images: AtomicU64::new(0),
+            deduplicated_bytes: AtomicU64::new(0),
+        })
+    }
+}
+
+// --- Processor ---
+/// Inlines screenshots into Firecrawl JSON documents. A processor is configured once and
+/// can then be shared between threads; all of its methods take `&self`.
+pub struct Processor {
+    config: ProcessorConfig,
+    fetcher: Arc<dyn ScreenshotFetcher>,
+    input_filter: InputFilter,
+    /// Images written so far, with the URL each of them was fetched from.
+    saved_images: Mutex<HashMap<PathBuf, String>>,
+    /// Content-addressed images, and whether each of them has been stored.
+    stored_images: Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<bool>>>>,
+    deduplicated_images: AtomicU64,
+    deduplicated_bytes: AtomicU64,
+}
+
+/// Images a content-addressed [`Processor`] did not write again because an identical image
+/// had been stored already, during this run or an earlier one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Deduplication {
+    pub images: u64,
+    pub bytes: u64,
+}
+
+impl Processor {
+    /// Starts a [`ProcessorConfig`]; see [`ProcessorConfig::new`].
+    pub fn builder(
+        image_dir: impl Into<PathBuf>,
+        output_dir: impl Into<PathBuf>,
+    ) -> ProcessorConfig {
+        ProcessorConfig::new(image_dir, output_dir)
+    }
+
+    pub fn config(&self) -> &ProcessorConfig {
+        &self.config
+    }
+
+    /// Totals of the images deduplicated so far; always zero unless the processor is
+    /// [content-addressed](ProcessorConfig::content_addressed).
+    pub fn deduplication(&self) -> Deduplication {
+        Deduplication {
+            images: self.deduplicated_images.load(Ordering::Relaxed),
+            bytes: self.deduplicated_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Processes one `.json` or `.jsonl` file, saving images to the configured image
+    /// directory and the rewritten file to the configured output directory, both through
+    /// the configured output sink.
+    /// Crawl/batch envelopes are detected and the pages in their `data` array are processed
+    /// concurrently.
+    ///
+    /// This and the other blocking methods run on a shared async runtime. Called from
+    /// within an async runtime they fail with [`AppError::AsyncContext`] instead; use
+    /// [`Processor::process_file_async`] there.
+    pub fn process_file(&self, json_path: &Path) -> FileProcessResult {
+        runtime::block_on(self.process_file_async(json_path))
+            .unwrap_or_else(|e| FileProcessResult::Failed(json_path.display().to_string(), e))
+    }
+
+    /// Async version of [`Processor::process_file`].
+    pub async fn process_file_async(&self, json_path: &Path) -> FileProcessResult {
+        self.process_file_into_async(json_path, &self.config.image_dir, &self.config.output_dir)
+            .await
+    }
+
+    /// Like [`Processor::process_file`], but writes to the given directories instead of the
+    /// configured ones.
+    pub fn process_file_into(
+        &self,
+        json_path: &Path,
+        image_dir_path: &Path,
+        output_dir_path: &Path,
+    ) -> FileProcessResult {
+        runtime::block_on(self.process_file_into_async(json_path, image_dir_path, output_dir_path))
+            .unwrap_or_else(|e| FileProcessResult::Failed(json_path.display().to_string(), e))
+    }
+
+    /// Async version of [`Processor::process_file_into`].
+    pub async fn process_file_into_async(
+        &self,
+        json_path: &Path,
+        image_dir_path: &Path,
+        output_dir_path: &Path,
+    ) -> FileProcessResult {
+        let job = FileJob {
+            json_path,
+            output_dir: output_dir_path,
+            sink: Arc::clone(&self.config.output_sink),
+            data_urls: Arc::new(DataUrls::default()),
+            explode_envelopes: self.config.explode_envelopes,
+            incremental: self.config.incremental,
+            events: self.config.events.as_ref(),
+            skip_reason: "No valid screenshot URL found",
+        };
+        let inliner = Inliner {
+            json_path,
+            image_dir_path,
+            output_dir_path,
+            processor: self,
+            data_urls: &job.data_urls,
+        };
+        rewrite_file(&job, &inliner).await
+    }
+
+    /// Inlines the screenshots of a parsed document in place, without reading or writing
+    /// JSON files. Images are still saved to the configured image directory through the
+    /// output sink; `source_path` only names them when the URL does not. Crawl/batch
+    /// envelopes are handled page by page.
+    ///
+    /// Called from within an async runtime, the document is left untouched and a single
+    /// report for the whole document (pointer `""`) holds [`AppError::AsyncContext`].
+    pub fn process_value(&self, document: &mut Value, source_path: &Path) -> Vec<FieldReport> {
+        runtime::block_on(self.process_value_async(document, source_path)).unwrap_or_else(|e| {
+            vec![FieldReport {
+                line: None,
+                pointer: String::new(),
+                attempts: 0,
+                status: FieldStatus::Failed(e),
+            }]
+        })
+    }
+
+    /// Async version of [`Processor::process_value`].
+    pub async fn process_value_async(
+        &self,
+        document: &mut Value,
+        source_path: &Path,
+    ) -> Vec<FieldReport> {
+        let data_urls = Arc::new(DataUrls::default());
+        let inliner = Inliner {
+            json_path: source_path,
+            image_dir_path: &self.config.image_dir,
+            output_dir_path: &self.config.output_dir,
+            processor: self,
+            data_urls: &data_urls,
+        };
+        let reports = rewrite_value(document, None, &inliner).await;
+        // The document is handed back, so its data URLs are built after all
+        let mut resolved = std::mem::take(document);
+        *document = run_blocking(move || {
+            data_urls.resolve(&mut resolved);
+            resolved
+        })
+        .await;
+        reports
+    }
+
+    /// Processes every matching file below `input_dir`, several at a time (see
+    /// [`ProcessorConfig::concurrency`]). The output directories mirror the input layout;
+    /// output directories inside `input_dir` are not walked. Results are returned sorted by
+    /// path.
+    pub fn process_dir(
+        &self,
+        input_dir: &Path,
+    ) -> Result<Vec<(PathBuf, FileProcessResult)>, AppError> {
+        runtime::block_on(self.process_dir_async(input_dir))?
+    }
+
+    /// Async version of [`Processor::process_dir`]. The directory walk runs on the runtime's
+    /// blocking pool.
+    pub async fn process_dir_async(
+        &self,
+        input_dir: &Path,
+    ) -> Result<Vec<(PathBuf, FileProcessResult)>, AppError> {
+        let job = DirJob {
+            input_dir,
+            image_dir: &self.config.image_dir,
+            output_dir: &self.config.output_dir,
+            input_filter: &self.input_filter,
+            // Content-addressed images are shared by all files, so they are not mirrored
+            mirror_images: !self.config.content_addressed,
+            concurrency: self.config.concurrency,
+            events: self.config.events.as_ref(),
+            on_file_result: self.config.on_file_result.as_ref(),
+        };
+        rewrite_dir(&job, |json_path, image_dir, output_dir| async move {
+            self.process_file_into_async(&json_path, &image_dir, &output_dir)
+                .await
+        })
+        .await
+    }
+}
+
+// --- Inlining ---
+/// Inlines the documents of one input file.
+struct Inliner<'a> {
+    json_path: &'a Path,
+    image_dir_path: &'a Path,
+    /// Directory of the output JSON, which relative image paths start from.
+    output_dir_path: &'a Path,
+    processor: &'a Processor,
+    /// Receives the saved images; the inlined fields hold their placeholders.
+    data_urls: &'a DataUrls,
+}
+
+impl DocumentRewriter for Inliner<'_> {
+    /// Inlines every screenshot field of one document and saves the fetched images to the
+    /// output sink. Fields without a URL are not reported.
+    async fn rewrite(
+        &self,
+        document: &mut Value,
+        pointer_prefix: &str,
+        line: Option<usize>,
+    ) -> Vec<FieldReport> {
+        let config = &self.processor.config;
+        let events = config.events.as_ref();
+        let source = DocumentSource {
+            path: self.json_path,
+            pointer_prefix,
+            line,
+        };
+        let inlined_fields = inline_value_at(
+            document,
+            self.processor.fetcher.as_ref(),
+            &config.inline,
+            &source,
+            events,
+            true,
+        )
+        .await;
+
+        let mut reports = Vec::with_capacity(inlined_fields.len());
+        for field in inlined_fields {
+            let status = match field.outcome {
+                InlineOutcome::Inlined(image) => {
+                    let relative_pointer = &field.pointer[pointer_prefix.len()..];
+                    let warn = |message: String| {
+                        events(&ProgressEvent::Warning {
+                            file: self.json_path.to_path_buf(),
+                            line,
+                            pointer: field.pointer.clone(),
+                            message,
+                        })
+                    };
+                    // Array entries have no field of their own to put a thumbnail or tiles next to
+                    let single_url = config
+                        .inline
+                        .screenshot_pointers
+                        .iter()
+                        .any(|pointer| pointer == relative_pointer);
+                    let (image, thumbnail) = match &config.thumbnail {
+                        Some(thumbnail) if single_url => render_thumbnail(thumbnail, image).await,
+                        _ => (image, Ok(None)),
+                    };
+                    let (image, tiles) = match &config.split {
+                        Some(Split::Tiles { height, field }) if single_url => {
+                            let (image, tiles) = self.render_tiles(*height, image).await;
+                            (image, tiles.map(|tiles| tiles.map(|tiles| (field, tiles))))
+                        }
+                        _ => (image, Ok(None)),
+                    };
+                    let source_url = image.source_url.clone();
+                    // Without the saved image the field is left as it was
+                    match self.save_image(image).await {
+                        Ok((new_value, status)) => {
+                            if let Some(value) = document.pointer_mut(relative_pointer) {
+                                *value = Value::String(new_value);
+                            }
+                            match thumbnail {
+                                Ok(Some((mime_type, bytes))) => {
+                                    let data_url = self.data_urls.defer(mime_type, bytes);
+                                    let name = Thumbnail::field_name(&field_name(relative_pointer));
+                                    insert_next_to(document, relative_pointer, name, data_url);
+                                }
+                                Ok(None) => {}
+                                Err(e) => warn(format!("no thumbnail: {}", e)),
+                            }
+                            let tiles = match (tiles, &status) {
+                                (
+                                    Ok(Some((tiles_field, Tiles { mime_type, images }))),
+                                    FieldStatus::Inlined { image_path }
+                                    | FieldStatus::Linked { image_path },
+                                ) => self
+                                    .save_tiles(image_path, &source_url, mime_type, images)
+                                    .await
+                                    .map(|values| Some((tiles_field, values))),
+                                (tiles, _) => tiles.map(|_| None),
+                            };
+                            match tiles {
+                                Ok(Some((tiles_field, values))) => {
+                                    let name = tiles_field.clone();
+                                    insert_next_to(document, relative_pointer, name, values);
+                                }
+                                Ok(None) => {}
+                                Err(e) => warn(format!("no tiles: {}", e)),
+                            }
+                            status
+                        }
+                        Err(e) => FieldStatus::Failed(e),
+                    }
+                }
+                InlineOutcome::UpToDate => FieldStatus::UpToDate,
+                InlineOutcome::Rejected(e) => FieldStatus::Rejected(e),
+                InlineOutcome::Failed(e) => FieldStatus::Failed(e),
+            };
+            if let FieldStatus::Failed(e) | FieldStatus::Rejected(e) = &status {
+                events(&ProgressEvent::Warning {
+                    file: self.json_path.to_path_buf(),
+                    line,
+                    pointer: field.pointer.clone(),
+                    message: format!("not inlined: {}", e),
+                });
+            }
+            reports.push(FieldReport {
+                line,
+                pointer: field.pointer,
+                attempts: field.attempts,
+                status,
+            });
+        }
+        reports
+    }
+
+    fn concurrency(&self) -> usize {
+        self.processor.config.concurrency
+    }
+}
+
+impl Inliner<'_> {
+    /// Resizes and transcodes an image if configured, writes it to the output sink (unless a
+    /// content-addressed copy exists already)
+    /// and returns the new value of its field, according to the configured
+    /// [`ScreenshotTarget`], together with the field's status.
+    async fn save_image(&self, image: InlinedImage) -> Result<(String, FieldStatus), AppError> {
+        let processor = self.processor;
+        let config = &processor.config;
+        let crop_height = config.split.as_ref().and_then(Split::crop_height);
+        let (image, inlined) =
+            if crop_height.is_some() || config.resize.is_some() || config.transcode.is_some() {
+                reencode_image(crop_height, config.resize, config.transcode.as_ref(), image).await?
+            } else {
+                (image, None)
+            };
+        let InlinedImage {
+            source_url,
+            filename,
+            mime_type,
+            bytes,
+        } = image;
+        let (stored_name, bytes) = if config.content_addressed {
+            run_blocking(move || (content_addressed_name(&filename, &bytes), bytes)).await
+        } else {
+            (PathBuf::from(filename), bytes)
+        };
+        let requested_path = self.image_dir_path.join(&stored_name);
+        // Claimed before writing, so that different images do not end up under the same name
+        let image_output_path = if config.content_addressed {
+            requested_path
+        } else {
+            let path = processor.claim_name(&requested_path, &source_url, config.on_collision)?;
+            if path != requested_path {
+                (config.events)(&ProgressEvent::Renamed {
+                    file: self.json_path.to_path_buf(),
+                    requested: requested_path,
+                    path: path.clone(),
+                });
+            }
+            path
+        };
+        let (bytes, reference, stored) = self.write_image(&image_output_path, bytes).await?;
+
+        let file = self.json_path.to_path_buf();
+        if stored {
+            processor
+                .deduplicated_images
+                .fetch_add(1, Ordering::Relaxed);
+            processor
+                .deduplicated_bytes
+                .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+            (config.events)(&ProgressEvent::Deduplicated {
+                file,
+                path: image_output_path.clone(),
+                bytes: bytes.len(),
+            });
+        } else {
+            (config.events)(&ProgressEvent::Saved {
+                file,
+                kind: OutputKind::Image,
+                path: image_output_path.clone(),
+            });
+        }
+
+        let image_path = image_output_path;
+        Ok(match reference {
+            Some(reference) => (reference, FieldStatus::Linked { image_path }),
+            None => {
+                let (mime_type, bytes) = inlined.unwrap_or((mime_type, bytes));
+                (
+                    self.data_urls.defer(mime_type, bytes),
+                    FieldStatus::Inlined { image_path },
+                )
+            }
+        })
+    }
+
+    /// Slices a fetched image into tiles on the blocking pool, handing the image back.
+    async fn render_tiles(
+        &self,
+        height: u32,
+        image: InlinedImage,
+    ) -> (InlinedImage, Result<Option<Tiles>, AppError>) {
+        let config = &self.processor.config;
+        let resize = config.resize;
+        let transcode = config.transcode.clone();
+        run_blocking(move || {
+            let tiles = render_tiles(
+                &image.source_url,
+                &image.mime_type,
+                &image.bytes,
+                height,
+                resize.as_ref(),
+                transcode.as_ref(),
+            );
+            (image, tiles)
+        })
+        .await
+    }
+
+    /// Writes the tiles of the image saved to `image_path` next to it, see [`Split::Tiles`],
+    /// and returns the values of the tiles field, which reference the tiles like the field
+    /// of the image references it.
+    async fn save_tiles(
+        &self,
+        image_path: &Path,
+        source_url: &str,
+        mime_type: String,
+        tiles: Vec<Vec<u8>>,
+    ) -> Result<Vec<Value>, AppError> {
+        let processor = self.processor;
+        let config = &processor.config;
+        let file_name = image_path.file_name().unwrap_or_default().to_string_lossy();
+        let mut values = Vec::with_capacity(tiles.len());
+        for (index, bytes) in tiles.into_iter().enumerate() {
+            let tile_name = tile_filename(&file_name, index + 1, &mime_type);
+            let (tile_path, bytes) = if config.content_addressed {
+                // Named after their own bytes, as tiles of another height differ
+                let (stored_name, bytes) =
+                    run_blocking(move || (content_addressed_name(&tile_name, &bytes), bytes)).await;
+                (self.image_dir_path.join(stored_name), bytes)
+            } else {
+                let requested_path = image_path.with_file_name(tile_name);
+                let tile_url = format!("{}#tile{}", source_url, index + 1);
+                let tile_path =
+                    processor.claim_name(&requested_path, &tile_url, config.on_collision)?;
+                (tile_path, bytes)
+            };
+            let (bytes, reference, stored) = self.write_image(&tile_path, bytes).await?;
+            if !stored {
+                (config.events)(&ProgressEvent::Saved {
+                    file: self.json_path.to_path_buf(),
+                    kind: OutputKind::Image,
+                    path: tile_path,
+                });
+            }
+            let value = match reference {
+                Some(reference) => reference,
+                None => self.data_urls.defer(mime_type.clone(), bytes),
+            };
+            values.push(Value::String(value));
+        }
+        Ok(values)
+    }
+
+    /// Writes an image to `path` through the output sink and returns its bytes, the reference
+    /// to it (see [`image_reference`]) and whether it was stored already. A content-addressed
+    /// image is only written once: fields holding the same image wait for the one writing
+    /// it, and write it themselves if that write fails, so no field references an image
+    /// that was never stored.
+    async fn write_image(
+        &self,
+        path: &Path,
+        bytes: Vec<u8>,
+    ) -> Result<(Vec<u8>, Option<String>, bool), AppError> {
+        let config = &self.processor.config;
+        let stored_image = config
+            .content_addressed
+            .then(|| self.processor.stored_image(path));
+        let mut stored = match &stored_image {
+            Some(stored_image) => Some(stored_image.lock().await),
+            None => None,
+        };
+        let stored_before = stored.as_deref().copied();
+
+        let sink = Arc::clone(&config.output_sink);
+        let target = config.target.clone();
+        let path = path.to_path_buf();
+        let output_dir = self.output_dir_path.to_path_buf();
+        let saved = run_blocking(move || {
+            // Images stored by an earlier run are found in the sink
+            let stored =
+                stored_before.is_some_and(|stored| stored || sink.modified(&path).is_some());
+            if !stored {
+                sink.write(OutputKind::Image, &path, &bytes)?;
+            }
+            let reference = image_reference(&target, &output_dir, &path, &bytes)?;
+            Ok::<_, AppError>((bytes, reference, stored))
+        })
+        .await?;
+        if let Some(stored) = &mut stored {
+            **stored = true;
+        }
+        Ok(saved)
+    }
+}
+
+/// The reference to the image saved to `path` that `target` calls for, or `None` for a data
+/// URL.
+fn image_reference(
+    target: &ScreenshotTarget,
+    output_dir: &Path,
+    path: &Path,
+    bytes: &[u8],
+) -> Result<Option<String>, AppError> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    Ok(match target {
+        ScreenshotTarget::DataUrl => None,
+        ScreenshotTarget::RelativePath => Some(relative_path(output_dir, path)?),
+        ScreenshotTarget::UrlTemplate(template) => {
+            Some(expand_url_template(template, &file_name, bytes))
+        }
+    })
+}
+
+impl Processor {
+    /// Whether the content-addressed image at `path` has been stored, behind a lock that is
+    /// held while it is being written.
+    fn stored_image(&self, path: &Path) -> Arc<tokio::sync::Mutex<bool>> {
+        let mut stored_images = self.stored_images.lock().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(stored_images.entry(path.to_path_buf()).or_default())
+    }
+
+    /// Reserves `path` for the image fetched from `source_url` and returns the path to write
+    /// it to. If a different image was saved under that name before, `on_collision` decides.
+    fn claim_name(
+        &self,
+        path: &Path,
+        source_url: &str,
+        on_collision: OnCollision,
+    ) -> Result<PathBuf, AppError> {
+        let mut saved_images = self.saved_images.lock().unwrap_or_else(|e| e.into_inner());
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut candidate = path.to_path_buf();
+        for number in 2.. {
+            match saved_images.get(&candidate) {
+                None => break,
+                Some(existing_url) if existing_url == source_url => return Ok(candidate),
+                Some(existing_url) => match on_collision {
+                    OnCollision::Suffix => {
+                        candidate = path.with_file_name(suffixed_filename(&file_name, number));
+                    }
+                    OnCollision::Error => {
+                        return Err(AppError::NameCollision {
+                            path: candidate,
+                            url: source_url.to_string(),
+                            existing_url: existing_url.clone(),
+                        });
+                    }
+                    OnCollision::Overwrite => break,
+                },
+            }
+        }
+        saved_images.insert(candidate.clone(), source_url.to_string());
+        Ok(candidate)
+    }
+}
+
+/// Crops, resizes and transcodes a fetched image on the blocking pool, see [`reencode`]. Returns
+/// the image to save and, when it is not the re-encoded one, the type and bytes to inline
+/// instead.
+async fn reencode_image(
+    crop_height: Option<u32>,
+    resize: Option<Resize>,
+    transcode: Option<&Transcode>,
+    image: InlinedImage,
+) -> Result<(InlinedImage, Option<(String, Vec<u8>)>), AppError> {
+    let keep_original = transcode.is_none_or(|transcode| transcode.keep_original);
+    let transcode = transcode.cloned();
+    let (mut image, reencoded) = run_blocking(move || {
+        let reencoded = reencode(
+            &image.source_url,
+            &image.mime_type,
+            &image.bytes,
+            crop_height,
+            resize.as_ref(),
+            transcode.as_ref(),
+        );
+        (image, reencoded)
+    })
+    .await;
+    let Some((mime_type, bytes)) = reencoded? else {
+        return Ok((image, None));
+    };
+    if keep_original {
+        return Ok((image, Some((mime_type, bytes))));
+    }
+    if let Some(extension) = extension_for_mime(&mime_type) {
+        image.filename = match image.filename.rsplit_once('.') {
+            Some((stem, _)) => format!("{}.{}", stem, extension),
+            None => format!("{}.{}", image.filename, extension),
+        };
+    }
+    image.mime_type = mime_type;
+    image.bytes = bytes;
+    Ok((image, None))
+}
+
+/// Renders the thumbnail of a fetched image on the blocking pool, handing the image back.
+async fn render_thumbnail(
+    thumbnail: &Thumbnail,
+    image: InlinedImage,
+) -> (InlinedImage, Result<Option<(String, Vec<u8>)>, AppError>) {
+    let thumbnail = thumbnail.clone();
+    run_blocking(move || {
+        let rendered = thumbnail.render(&image.source_url, &image.bytes);
+        (image, rendered)
+    })
+    .await
+}
+
+/// The name of the field at `pointer`, unescaped.
+fn field_name(pointer: &str) -> String {
+    let field = pointer.rsplit_once('/').map_or(pointer, |(_, field)| field);
+    field.replace("~1", "/").replace("~0", "~")
+}
+
+/// Stores `value` in the field `name` of the object holding the field at `pointer`.
+fn insert_next_to(document: &mut Value, pointer: &str, name: String, value: impl Into<Value>) {
+    let Some((parent, _)) = pointer.rsplit_once('/') else {
+        return;
+    };
+    if let Some(Value::Object(parent)) = document.pointer_mut(parent) {
+        parent.insert(name, value.into());
+    }
+}
+
+/// `9f/9f86d0…08.png`: the SHA-256 of the image, sharded by its first two hex digits.
+fn content_addressed_name(image_filename: &str, bytes: &[u8]) -> PathBuf {
+    let sha256 = sha256_hex(bytes);
+    let extension = image_extension(image_filename, bytes);
+    Path::new(&sha256[..2]).join(format!("{}.{}", sha256, extension))
+}
@@ -0,0 +1,109 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+// --- Token bucket ---
+/// Refills at `rate` tokens per second up to `capacity`. Callers reserve a token and wait
+/// for it outside the lock, so the balance can go negative while requests are queued.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        // Allow a burst of up to one second's worth of requests, but at least one
+        let capacity = rate.max(1.0);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes one token and returns how long the caller has to wait before using it.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+// --- Rate limiter ---
+/// Caps the number of requests per second, globally and per host. One limiter is shared
+/// by every worker thread; the default limiter lets everything through.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    global: Option<Mutex<TokenBucket>>,
+    per_host_rps: Option<f64>,
+    hosts: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing at most `max_rps` requests per second in total and
+    /// `max_rps_per_host` per host. `None` (or a non-positive rate) leaves that limit off.
+    pub fn new(max_rps: Option<f64>, max_rps_per_host: Option<f64>) -> Self {
+        let positive = |rate: Option<f64>| rate.filter(|r| *r > 0.0);
+        RateLimiter {
+            global: positive(max_rps).map(|rate| Mutex::new(TokenBucket::new(rate))),
+            per_host_rps: positive(max_rps_per_host),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Blocks until a request to `url` is allowed. URLs without a host only count
+    /// against the global limit.
+    pub fn acquire(&self, url: &str) {
+        let delay = self.reserve(url, Instant::now());
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+
+    /// Like [`RateLimiter::acquire`], but waits without blocking the thread. Must be called
+    /// from within a tokio runtime; time is read from the runtime's clock, so a paused
+    /// runtime (`tokio::time::pause`) controls it.
+    pub async fn acquire_async(&self, url: &str) {
+        let delay = self.reserve(url, tokio::time::Instant::now().into_std());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Reserves a slot for a request to `url` made at `now` and returns how long the request
+    /// has to wait for it. [`RateLimiter::acquire`] is this followed by a sleep.
+    pub fn reserve(&self, url: &str, now: Instant) -> Duration {
+        let mut delay = Duration::ZERO;
+        if let Some(global) = &self.global {
+            let mut bucket = global.lock().unwrap_or_else(|e| e.into_inner());
+            delay = delay.max(bucket.reserve(now));
+        }
+        if let Some(rate) = self.per_host_rps {
+            let host = Url::parse(url)
+                .ok()
+                .and_then(|parsed| parsed.host_str().map(str::to_ascii_lowercase));
+            if let Some(host) = host {
+                let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+                let bucket = hosts.entry(host).or_insert_with(|| TokenBucket::new(rate));
+                delay = delay.max(bucket.reserve(now));
+            }
+        }
+        delay
+    }
+}
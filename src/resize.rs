@@ -0,0 +1,124 @@
+use image::{DynamicImage, ImageError, imageops::FilterType};
+use std::str::FromStr;
+
+use crate::{
+    AppError,
+    transcode::{DEFAULT_QUALITY, TranscodeFormat, encode},
+};
+
+/// Appended to the name of a screenshot field to name the field holding its thumbnail:
+/// `screenshot` gets a `screenshotThumbnail`.
+pub const THUMBNAIL_FIELD_SUFFIX: &str = "Thumbnail";
+
+// --- Resizing ---
+/// Bounds images are scaled down to fit, keeping their aspect ratio. Images are never
+/// scaled up; an unset bound does not limit that dimension.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Resize {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+impl Resize {
+    /// Size a `width`x`height` image is scaled down to, or `None` if it fits already.
+    pub fn fit(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let scale = |max: Option<u32>, size: u32| match max {
+            Some(max) if size > max => f64::from(max.max(1)) / f64::from(size),
+            _ => 1.0,
+        };
+        let scale = scale(self.max_width, width).min(scale(self.max_height, height));
+        if scale >= 1.0 {
+            return None;
+        }
+        let scaled = |size: u32| ((f64::from(size) * scale).round() as u32).max(1);
+        Some((scaled(width), scaled(height)))
+    }
+
+    /// `image` scaled down to fit, or `None` if it fits already.
+    pub(crate) fn apply(&self, image: &DynamicImage) -> Option<DynamicImage> {
+        let (width, height) = self.fit(image.width(), image.height())?;
+        Some(image.resize_exact(width, height, FilterType::CatmullRom))
+    }
+}
+
+impl FromStr for Resize {
+    type Err = AppError;
+
+    /// Parses `WIDTHxHEIGHT`, where either bound may be left out: `400`, `400x300`, `x300`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            AppError::InvalidInput(format!(
+                "Invalid size '{}': expected WIDTH, WIDTHxHEIGHT or xHEIGHT in pixels",
+                value
+            ))
+        };
+        let (width, height) = value.split_once(['x', 'X']).unwrap_or((value, ""));
+        let bound = |bound: &str| match bound.trim() {
+            "" => Ok(None),
+            bound => match bound.parse::<u32>() {
+                Ok(0) | Err(_) => Err(invalid()),
+                Ok(bound) => Ok(Some(bound)),
+            },
+        };
+        let resize = Resize {
+            max_width: bound(width)?,
+            max_height: bound(height)?,
+        };
+        if resize == Resize::default() {
+            return Err(invalid());
+        }
+        Ok(resize)
+    }
+}
+
+// --- Thumbnails ---
+/// A small preview added next to each inlined screenshot field, as a data URL in a field
+/// named after it (see [`THUMBNAIL_FIELD_SUFFIX`]). Thumbnails are only made for fields
+/// holding a single URL, not for the entries of arrays such as `actions.screenshots`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    /// Bounds the screenshot is scaled down to fit.
+    pub size: Resize,
+    pub format: TranscodeFormat,
+    /// Encoder quality from 1 (smallest output) to 100 (best quality).
+    pub quality: u8,
+}
+
+impl Thumbnail {
+    /// WebP thumbnails of `size` at [`DEFAULT_QUALITY`].
+    pub fn new(size: Resize) -> Self {
+        Thumbnail {
+            size,
+            format: TranscodeFormat::WebP,
+            quality: DEFAULT_QUALITY,
+        }
+    }
+
+    /// Name of the field holding the thumbnail of the screenshot field `field`.
+    pub fn field_name(field: &str) -> String {
+        format!("{}{}", field, THUMBNAIL_FIELD_SUFFIX)
+    }
+
+    /// Renders the thumbnail of the image fetched from `url`, returning its MIME type and
+    /// bytes, or `None` if the image cannot be decoded here (e.g. AVIF). CPU-bound; run it
+    /// on the blocking pool.
+    pub(crate) fn render(
+        &self,
+        url: &str,
+        bytes: &[u8],
+    ) -> Result<Option<(String, Vec<u8>)>, AppError> {
+        let failed = |reason: String| AppError::Transcode {
+            url: url.to_string(),
+            reason,
+        };
+        let image = match image::load_from_memory(bytes) {
+            Ok(image) => image,
+            Err(ImageError::Unsupported(_)) => return Ok(None),
+            Err(e) => return Err(failed(format!("could not decode the image: {}", e))),
+        };
+        let thumbnail = self.size.apply(&image).unwrap_or(image);
+        let encoded = encode(&thumbnail, self.format, self.quality)
+            .map_err(|reason| failed(format!("could not encode the thumbnail: {}", reason)))?;
+        Ok(Some((self.format.mime_type().to_string(), encoded)))
+    }
+}
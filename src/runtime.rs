@@ -0,0 +1,50 @@
+use std::{future::Future, panic, sync::OnceLock};
+use tokio::runtime::{Handle, Runtime};
+
+use crate::AppError;
+
+// --- Async runtime ---
+/// Runtime behind the blocking API. It is created on first use and lives for the rest of
+/// the process, so it is never shut down from within one of its own tasks.
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Runs `future` to completion on the shared runtime, blocking the calling thread.
+///
+/// # Errors
+/// [`AppError::AsyncContext`] when called from within an async runtime, where blocking
+/// would stall (or, on a current-thread runtime, deadlock) its worker threads.
+pub(crate) fn block_on<F: Future>(future: F) -> Result<F::Output, AppError> {
+    if Handle::try_current().is_ok() {
+        return Err(AppError::AsyncContext);
+    }
+    Ok(RUNTIME
+        .get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .thread_name("fcjp-worker")
+                .enable_all()
+                .build()
+                .expect("failed to start the async runtime")
+        })
+        .block_on(future))
+}
+
+/// Runs CPU-bound or blocking work (base64 encoding, JSON parsing and serialization, file
+/// writes) on the blocking pool of the current runtime, so it does not stall downloads.
+/// Outside a runtime the work runs inline.
+pub(crate) async fn run_blocking<T, F>(work: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let handle = match Handle::try_current() {
+        Ok(handle) => handle,
+        Err(_) => return work(),
+    };
+    match handle.spawn_blocking(work).await {
+        Ok(output) => output,
+        Err(e) => match e.try_into_panic() {
+            Ok(payload) => panic::resume_unwind(payload),
+            Err(e) => panic!("blocking task did not complete: {}", e),
+        },
+    }
+}
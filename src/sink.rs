@@ -0,0 +1,322 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufWriter, Seek, Write},
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::AppError;
+
+// --- Output sinks ---
+/// What an output written to an [`OutputSink`] holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputKind {
+    /// A screenshot saved next to the rewritten JSON.
+    Image,
+    /// A rewritten `.json` or `.jsonl` file, or one page of an exploded envelope.
+    Json,
+}
+
+/// Where images and rewritten JSON files go. Outputs are addressed by the path they would
+/// have on disk (e.g. `out/images/page.png`); sinks that do not write to the filesystem use
+/// it as a key. Sinks are shared between worker threads, so writes may arrive concurrently.
+///
+/// Any `Fn(OutputKind, &Path, &[u8]) -> Result<(), AppError>` closure is a sink, which lets
+/// outputs be streamed straight into another pipeline.
+pub trait OutputSink: Send + Sync {
+    /// Stores `bytes` as the output at `path`, replacing any earlier output there.
+    fn write(&self, kind: OutputKind, path: &Path, bytes: &[u8]) -> Result<(), AppError>;
+
+    /// Stores the output that `write_output` writes into the given writer. Rewritten JSON
+    /// files are written this way, so that large documents are never held in memory as a
+    /// whole. The default collects the output and passes it to [`OutputSink::write`];
+    /// sinks that can write incrementally should hand out their writer instead.
+    fn write_stream(
+        &self,
+        kind: OutputKind,
+        path: &Path,
+        write_output: &mut WriteOutput,
+    ) -> Result<(), AppError> {
+        let mut bytes = Vec::new();
+        write_output(&mut bytes).map_err(|source| write_error(path, source))?;
+        self.write(kind, path, &bytes)
+    }
+
+    /// When the output at `path` was last written, if the sink can tell. Incremental mode
+    /// never skips an input whose output reports no time.
+    fn modified(&self, _path: &Path) -> Option<SystemTime> {
+        None
+    }
+}
+
+/// Produces the contents of a streamed output, see [`OutputSink::write_stream`].
+pub type WriteOutput<'a> = dyn FnMut(&mut dyn Write) -> io::Result<()> + Send + 'a;
+
+impl<F> OutputSink for F
+where
+    F: Fn(OutputKind, &Path, &[u8]) -> Result<(), AppError> + Send + Sync,
+{
+    fn write(&self, kind: OutputKind, path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+        self(kind, path, bytes)
+    }
+}
+
+/// Writes outputs to the filesystem, creating missing directories. This is the default.
+/// Streamed outputs go to a hidden temporary file next to their path first and are only
+/// renamed into place once complete.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsSink;
+
+impl OutputSink for FsSink {
+    fn write(&self, _kind: OutputKind, path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+        create_parent_dir(path)?;
+        fs::write(path, bytes).map_err(|source| write_error(path, source))
+    }
+
+    fn write_stream(
+        &self,
+        _kind: OutputKind,
+        path: &Path,
+        write_output: &mut WriteOutput,
+    ) -> Result<(), AppError> {
+        create_parent_dir(path)?;
+        // The output is only moved into place once it is complete, so that an interrupted
+        // or failed write never leaves a truncated file that incremental mode would skip
+        let temp_path = temp_output_path(path);
+        let written = fs::File::create(&temp_path)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                write_output(&mut writer)?;
+                writer.flush()
+            })
+            .and_then(|()| fs::rename(&temp_path, path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        written.map_err(|source| write_error(path, source))
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+}
+
+/// `out/.page.json.3f2a…tmp`: a hidden file next to `path` that its output is written to
+/// before it is renamed over `path`.
+fn temp_output_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{:016x}.tmp", file_name, fastrand::u64(..)))
+}
+
+/// Keeps outputs in memory, keyed by path.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    outputs: Mutex<BTreeMap<PathBuf, (OutputKind, Vec<u8>)>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        MemorySink::default()
+    }
+
+    /// The bytes last written to `path`.
+    pub fn get(&self, path: &Path) -> Option<Vec<u8>> {
+        let outputs = self.outputs.lock().unwrap_or_else(|e| e.into_inner());
+        outputs.get(path).map(|(_, bytes)| bytes.clone())
+    }
+
+    /// Paths of the outputs of the given kind, sorted.
+    pub fn paths(&self, kind: OutputKind) -> Vec<PathBuf> {
+        let outputs = self.outputs.lock().unwrap_or_else(|e| e.into_inner());
+        outputs
+            .iter()
+            .filter(|(_, (output_kind, _))| *output_kind == kind)
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Removes and returns every output written so far.
+    pub fn take(&self) -> BTreeMap<PathBuf, (OutputKind, Vec<u8>)> {
+        let mut outputs = self.outputs.lock().unwrap_or_else(|e| e.into_inner());
+        std::mem::take(&mut *outputs)
+    }
+}
+
+impl OutputSink for MemorySink {
+    fn write(&self, kind: OutputKind, path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+        let mut outputs = self.outputs.lock().unwrap_or_else(|e| e.into_inner());
+        outputs.insert(path.to_path_buf(), (kind, bytes.to_vec()));
+        Ok(())
+    }
+
+    fn write_stream(
+        &self,
+        kind: OutputKind,
+        path: &Path,
+        write_output: &mut WriteOutput,
+    ) -> Result<(), AppError> {
+        // The output is kept anyway, so it is collected right where it stays
+        let mut bytes = Vec::new();
+        write_output(&mut bytes).map_err(|source| write_error(path, source))?;
+        let mut outputs = self.outputs.lock().unwrap_or_else(|e| e.into_inner());
+        outputs.insert(path.to_path_buf(), (kind, bytes));
+        Ok(())
+    }
+}
+
+/// Appends outputs to a tar archive. Entries are named by their path relative to `base`
+/// (e.g. `images/page.png` for `out/images/page.png` with base `out`); call
+/// [`TarSink::finish`] once processing is done to write the end-of-archive marker.
+pub struct TarSink<W: Write + Send> {
+    base: PathBuf,
+    builder: Mutex<Option<tar::Builder<W>>>,
+}
+
+impl<W: Write + Send> TarSink<W> {
+    pub fn new(writer: W, base: impl Into<PathBuf>) -> Self {
+        TarSink {
+            base: base.into(),
+            builder: Mutex::new(Some(tar::Builder::new(writer))),
+        }
+    }
+
+    /// Completes the archive and returns the underlying writer. Later writes fail.
+    pub fn finish(&self) -> Result<W, AppError> {
+        let builder = self
+            .builder
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .ok_or_else(|| archive_finished(&self.base))?;
+        builder.into_inner().map_err(|source| AppError::Write {
+            path: self.base.clone(),
+            source,
+        })
+    }
+}
+
+impl<W: Write + Send> OutputSink for TarSink<W> {
+    fn write(&self, _kind: OutputKind, path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(unix_time_now());
+        let mut builder = self.builder.lock().unwrap_or_else(|e| e.into_inner());
+        let builder = builder.as_mut().ok_or_else(|| archive_finished(path))?;
+        builder
+            .append_data(&mut header, archive_entry_name(&self.base, path), bytes)
+            .map_err(|source| AppError::Write {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+}
+
+/// Adds outputs to a zip archive, named like the entries of a [`TarSink`]. Call
+/// [`ZipSink::finish`] once processing is done to write the central directory.
+pub struct ZipSink<W: Write + Seek + Send> {
+    base: PathBuf,
+    writer: Mutex<Option<zip::ZipWriter<W>>>,
+}
+
+impl<W: Write + Seek + Send> ZipSink<W> {
+    pub fn new(writer: W, base: impl Into<PathBuf>) -> Self {
+        ZipSink {
+            base: base.into(),
+            writer: Mutex::new(Some(zip::ZipWriter::new(writer))),
+        }
+    }
+
+    /// Completes the archive and returns the underlying writer. Later writes fail.
+    pub fn finish(&self) -> Result<W, AppError> {
+        let writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .ok_or_else(|| archive_finished(&self.base))?;
+        writer.finish().map_err(|e| zip_error(&self.base, e))
+    }
+}
+
+impl<W: Write + Seek + Send> OutputSink for ZipSink<W> {
+    fn write(&self, kind: OutputKind, path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+        self.write_stream(kind, path, &mut |writer| writer.write_all(bytes))
+    }
+
+    fn write_stream(
+        &self,
+        kind: OutputKind,
+        path: &Path,
+        write_output: &mut WriteOutput,
+    ) -> Result<(), AppError> {
+        // Images are compressed already; deflating them again only costs time
+        let method = match kind {
+            OutputKind::Image => zip::CompressionMethod::Stored,
+            OutputKind::Json => zip::CompressionMethod::Deflated,
+        };
+        let options = zip::write::SimpleFileOptions::default().compression_method(method);
+        let name = archive_entry_name(&self.base, path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let writer = writer.as_mut().ok_or_else(|| archive_finished(path))?;
+        writer
+            .start_file(name, options)
+            .map_err(|e| zip_error(path, e))?;
+        write_output(writer).map_err(|source| write_error(path, source))
+    }
+}
+
+/// `path` relative to `base`, or with its root and any `..` removed if it lies elsewhere.
+fn archive_entry_name(base: &Path, path: &Path) -> PathBuf {
+    let relative = path.strip_prefix(base).unwrap_or(path);
+    relative
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+fn create_parent_dir(path: &Path) -> Result<(), AppError> {
+    match path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        Some(parent) => fs::create_dir_all(parent).map_err(|source| write_error(path, source)),
+        None => Ok(()),
+    }
+}
+
+fn write_error(path: &Path, source: io::Error) -> AppError {
+    AppError::Write {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn archive_finished(path: &Path) -> AppError {
+    AppError::Write {
+        path: path.to_path_buf(),
+        source: io::Error::other("archive is already finished"),
+    }
+}
+
+fn zip_error(path: &Path, error: zip::result::ZipError) -> AppError {
+    let source = match error {
+        zip::result::ZipError::Io(source) => source,
+        other => io::Error::other(other),
+    };
+    AppError::Write {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
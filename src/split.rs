@@ -0,0 +1,128 @@
+use image::ImageError;
+
+use crate::{
+    AppError,
+    extract::extension_for_mime,
+    resize::Resize,
+    transcode::{Transcode, encode, encode_as},
+};
+
+/// Field the tiles of a screenshot are stored in when no other is configured.
+pub const DEFAULT_TILES_FIELD: &str = "screenshotTiles";
+
+// --- Tall screenshots ---
+/// How tall screenshots, such as Firecrawl's `screenshot@fullPage` captures, are cut down
+/// for consumers that reject them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Split {
+    /// Inline only the top `height` pixels, above the fold, of taller images. Like resized
+    /// images, cropped ones are only saved to the image directory if
+    /// [`Transcode::keep_original`] is false.
+    Crop { height: u32 },
+    /// Slice images into tiles `height` pixels tall, the last one possibly shorter, and add
+    /// them as an array in the field named `field`, next to the screenshot field. The tiles
+    /// are saved to the image directory next to the screenshot, e.g. as `shot-tile1.png`,
+    /// and referenced like the screenshot is (see [`crate::ScreenshotTarget`]).
+    /// Content-addressed tiles are named by the SHA-256 of their own bytes instead. Only
+    /// fields holding a single URL are tiled, not the entries of arrays such as
+    /// `actions.screenshots`.
+    Tiles { height: u32, field: String },
+}
+
+impl Split {
+    /// Tiles of `height` pixels in the [`DEFAULT_TILES_FIELD`].
+    pub fn tiles(height: u32) -> Self {
+        Split::Tiles {
+            height,
+            field: DEFAULT_TILES_FIELD.to_string(),
+        }
+    }
+
+    /// Height images are cropped to, if any.
+    pub(crate) fn crop_height(&self) -> Option<u32> {
+        match self {
+            Split::Crop { height } => Some(*height),
+            Split::Tiles { .. } => None,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), AppError> {
+        match self {
+            Split::Crop { height: 0 } | Split::Tiles { height: 0, .. } => Err(
+                AppError::InvalidInput("Crop and tile heights must be at least 1".to_string()),
+            ),
+            Split::Tiles { field, .. } if field.is_empty() => Err(AppError::InvalidInput(
+                "The tiles field name must not be empty".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The tiles of an image, from top to bottom.
+pub(crate) struct Tiles {
+    pub mime_type: String,
+    pub images: Vec<Vec<u8>>,
+}
+
+/// Slices the image of type `mime_type` fetched from `url` into tiles `height` pixels tall,
+/// after scaling it down to fit `resize`. The tiles are encoded as `transcode` asks, or in
+/// the image's own format. Returns `None` if the image cannot be decoded here (e.g. AVIF).
+/// CPU-bound; run it on the blocking pool.
+pub(crate) fn render_tiles(
+    url: &str,
+    mime_type: &str,
+    bytes: &[u8],
+    height: u32,
+    resize: Option<&Resize>,
+    transcode: Option<&Transcode>,
+) -> Result<Option<Tiles>, AppError> {
+    let failed = |reason: String| AppError::Transcode {
+        url: url.to_string(),
+        reason,
+    };
+    let image = match image::load_from_memory(bytes) {
+        Ok(image) => image,
+        Err(ImageError::Unsupported(_)) => return Ok(None),
+        Err(e) => return Err(failed(format!("could not decode the image: {}", e))),
+    };
+    let image = resize
+        .and_then(|resize| resize.apply(&image))
+        .unwrap_or(image);
+    let height = height.max(1);
+    let target = transcode.filter(|transcode| {
+        transcode
+            .format
+            .fits(image.width(), height.min(image.height()))
+    });
+    let mime_type = target.map_or(mime_type, |transcode| transcode.format.mime_type());
+
+    let mut images = Vec::new();
+    for top in (0..image.height()).step_by(height as usize) {
+        let tile = image.crop_imm(0, top, image.width(), height.min(image.height() - top));
+        let encoded = match target {
+            Some(transcode) => encode(&tile, transcode.format, transcode.quality),
+            None => encode_as(&tile, mime_type),
+        };
+        images.push(
+            encoded.map_err(|reason| failed(format!("could not encode a tile: {}", reason)))?,
+        );
+    }
+    Ok(Some(Tiles {
+        mime_type: mime_type.to_string(),
+        images,
+    }))
+}
+
+/// `shot-tile2.png`: the name of the `number`th tile (from 1) of the image `file_name`, with
+/// the extension of the tiles' `mime_type`.
+pub(crate) fn tile_filename(file_name: &str, number: usize, mime_type: &str) -> String {
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (file_name, None),
+    };
+    match extension_for_mime(mime_type).or(extension) {
+        Some(extension) => format!("{}-tile{}.{}", stem, number, extension),
+        None => format!("{}-tile{}", stem, number),
+    }
+}
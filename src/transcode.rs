@@ -0,0 +1,230 @@
+use image::{
+    DynamicImage, ImageError, ImageFormat,
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder},
+};
+use std::{io::Cursor, str::FromStr};
+
+use crate::{AppError, extract::extension_for_mime, resize::Resize};
+
+/// Encoder quality used when none is given.
+pub const DEFAULT_QUALITY: u8 = 80;
+
+/// Largest width and height a WebP image can have.
+const WEBP_MAX_DIMENSION: u32 = 16_383;
+
+/// AVIF encoder speed, from 1 (slowest, smallest output) to 10. Screenshots are large, so
+/// this trades a little size for a lot of time.
+const AVIF_SPEED: u8 = 8;
+
+// --- Transcoding ---
+/// Formats images can be transcoded to before they are inlined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscodeFormat {
+    Jpeg,
+    WebP,
+    Avif,
+}
+
+impl TranscodeFormat {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            TranscodeFormat::Jpeg => "image/jpeg",
+            TranscodeFormat::WebP => "image/webp",
+            TranscodeFormat::Avif => "image/avif",
+        }
+    }
+
+    /// Conventional file extension, e.g. `webp`.
+    pub fn extension(self) -> &'static str {
+        extension_for_mime(self.mime_type()).unwrap_or("bin")
+    }
+
+    /// The format with MIME type `mime_type`, if it is one of these.
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        [
+            TranscodeFormat::Jpeg,
+            TranscodeFormat::WebP,
+            TranscodeFormat::Avif,
+        ]
+        .into_iter()
+        .find(|format| format.mime_type().eq_ignore_ascii_case(mime_type))
+    }
+
+    /// Whether the format can hold a `width`x`height` image.
+    pub fn fits(self, width: u32, height: u32) -> bool {
+        self != TranscodeFormat::WebP
+            || (width <= WEBP_MAX_DIMENSION && height <= WEBP_MAX_DIMENSION)
+    }
+}
+
+impl FromStr for TranscodeFormat {
+    type Err = AppError;
+
+    /// Parses `jpeg` (or `jpg`), `webp` or `avif`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(TranscodeFormat::Jpeg),
+            "webp" => Ok(TranscodeFormat::WebP),
+            "avif" => Ok(TranscodeFormat::Avif),
+            other => Err(AppError::InvalidInput(format!(
+                "Unknown transcode format '{}': expected 'jpeg', 'webp' or 'avif'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Re-encodes downloaded images before they are inlined, to keep data URLs small: a
+/// lossless PNG screenshot is often several times the size of a lossy WebP or AVIF.
+///
+/// Images are kept as they are when they have the target type already, when the target
+/// format cannot hold their dimensions (WebP is limited to 16383 pixels per side), when
+/// their format cannot be decoded here (e.g. AVIF), or when re-encoding would not make
+/// them smaller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcode {
+    pub format: TranscodeFormat,
+    /// Encoder quality from 1 (smallest output) to 100 (best quality).
+    pub quality: u8,
+    /// Save the downloaded image to the image directory untouched, and only inline the
+    /// transcoded (and possibly resized) one. When false, the transcoded image is saved
+    /// instead, with an extension that matches its format.
+    pub keep_original: bool,
+}
+
+impl Transcode {
+    /// Transcoding to `format` at [`DEFAULT_QUALITY`], keeping the original.
+    pub fn new(format: TranscodeFormat) -> Self {
+        Transcode {
+            format,
+            quality: DEFAULT_QUALITY,
+            keep_original: true,
+        }
+    }
+}
+
+/// Re-encodes the image of type `mime_type` fetched from `url` for inlining: cropped to its
+/// top `crop_height` pixels, scaled down to fit `resize` and converted as configured by
+/// `transcode`. Returns the new MIME type and bytes, or `None` if the image is to be kept as
+/// it is, see [`Transcode`]. Cropped and resized images are kept in their own format unless
+/// they are transcoded. CPU-bound; run it on the blocking pool.
+pub(crate) fn reencode(
+    url: &str,
+    mime_type: &str,
+    bytes: &[u8],
+    crop_height: Option<u32>,
+    resize: Option<&Resize>,
+    transcode: Option<&Transcode>,
+) -> Result<Option<(String, Vec<u8>)>, AppError> {
+    let has_target_type =
+        |transcode: &Transcode| transcode.format.mime_type().eq_ignore_ascii_case(mime_type);
+    if crop_height.is_none() && resize.is_none() && transcode.is_none_or(has_target_type) {
+        return Ok(None);
+    }
+    let failed = |reason: String| AppError::Transcode {
+        url: url.to_string(),
+        reason,
+    };
+    let image = match image::load_from_memory(bytes) {
+        Ok(image) => image,
+        Err(ImageError::Unsupported(_)) => return Ok(None),
+        Err(e) => return Err(failed(format!("could not decode the image: {}", e))),
+    };
+    let cropped = crop_height.and_then(|height| crop(&image, height));
+    let source = cropped.as_ref().unwrap_or(&image);
+    let resized = resize.and_then(|resize| resize.apply(source)).or(cropped);
+    if resized.is_none() && transcode.is_none_or(has_target_type) {
+        return Ok(None);
+    }
+    let image = resized.as_ref().unwrap_or(&image);
+
+    let target = transcode.filter(|transcode| transcode.format.fits(image.width(), image.height()));
+    let (mime_type, encoded) = match target {
+        Some(transcode) => (
+            transcode.format.mime_type(),
+            encode(image, transcode.format, transcode.quality),
+        ),
+        None if resized.is_none() => return Ok(None),
+        None => (mime_type, encode_as(image, mime_type)),
+    };
+    let encoded =
+        encoded.map_err(|reason| failed(format!("could not encode the image: {}", reason)))?;
+    // A resized or cropped image is used even if it is larger, which only happens at a
+    // higher quality
+    if resized.is_none() && encoded.len() >= bytes.len() {
+        return Ok(None);
+    }
+    Ok(Some((mime_type.to_string(), encoded)))
+}
+
+/// Encodes `image` as `format` at `quality` (clamped to 1..=100).
+pub(crate) fn encode(
+    image: &DynamicImage,
+    format: TranscodeFormat,
+    quality: u8,
+) -> Result<Vec<u8>, String> {
+    if !format.fits(image.width(), image.height()) {
+        return Err(format!(
+            "{}x{} is too large for {}",
+            image.width(),
+            image.height(),
+            format.mime_type()
+        ));
+    }
+    let quality = quality.clamp(1, 100);
+    match format {
+        TranscodeFormat::WebP => encode_webp(image, quality),
+        TranscodeFormat::Jpeg => encode_jpeg(image, quality),
+        TranscodeFormat::Avif => encode_avif(image, quality),
+    }
+}
+
+/// The top `height` pixels of `image`, or `None` if it is no taller.
+pub(crate) fn crop(image: &DynamicImage, height: u32) -> Option<DynamicImage> {
+    (image.height() > height).then(|| image.crop_imm(0, 0, image.width(), height.max(1)))
+}
+
+/// Encodes `image` in the format with MIME type `mime_type`, lossy formats at
+/// [`DEFAULT_QUALITY`].
+pub(crate) fn encode_as(image: &DynamicImage, mime_type: &str) -> Result<Vec<u8>, String> {
+    if let Some(format) = TranscodeFormat::from_mime_type(mime_type) {
+        return encode(image, format, DEFAULT_QUALITY);
+    }
+    let format = ImageFormat::from_mime_type(mime_type)
+        .ok_or_else(|| format!("{} cannot be encoded", mime_type))?;
+    let mut encoded = Cursor::new(Vec::new());
+    image
+        .write_to(&mut encoded, format)
+        .map_err(|e| e.to_string())?;
+    Ok(encoded.into_inner())
+}
+
+fn encode_webp(image: &DynamicImage, quality: u8) -> Result<Vec<u8>, String> {
+    let rgba = image.to_rgba8();
+    let encoder = webp::Encoder::from_rgba(&rgba, image.width(), image.height());
+    let encoded = encoder
+        .encode_simple(false, f32::from(quality))
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(encoded.to_vec())
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8) -> Result<Vec<u8>, String> {
+    let mut encoded = Vec::new();
+    // JPEG has no alpha channel; screenshots are opaque anyway
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, quality))
+        .map_err(|e| e.to_string())?;
+    Ok(encoded)
+}
+
+fn encode_avif(image: &DynamicImage, quality: u8) -> Result<Vec<u8>, String> {
+    let mut encoded = Vec::new();
+    image
+        .write_with_encoder(AvifEncoder::new_with_speed_quality(
+            &mut encoded,
+            AVIF_SPEED,
+            quality,
+        ))
+        .map_err(|e| e.to_string())?;
+    Ok(encoded)
+}
@@ -0,0 +1,87 @@
+use image::{ImageError, ImageReader};
+use infer::MatcherType;
+use std::io::Cursor;
+
+use crate::{AppError, extract::mime_for_extension};
+
+/// Largest width and height accepted by default, in pixels: the most JPEG can encode.
+pub const DEFAULT_MAX_DIMENSION: u32 = 65_535;
+
+// --- Payload validation ---
+/// Checks that a fetched payload is an image before it is saved or inlined. Expired
+/// screenshot URLs typically answer `200 OK` with an HTML or XML error page, which would
+/// otherwise end up in the output as a broken image.
+#[derive(Debug, Clone)]
+pub struct ImageValidation {
+    /// Accepted types, as MIME types such as `image/png` or extensions such as `webp`.
+    /// Empty accepts every image type.
+    pub allowed_types: Vec<String>,
+    /// Largest accepted width in pixels.
+    pub max_width: u32,
+    /// Largest accepted height in pixels.
+    pub max_height: u32,
+}
+
+impl Default for ImageValidation {
+    fn default() -> Self {
+        ImageValidation {
+            allowed_types: Vec::new(),
+            max_width: DEFAULT_MAX_DIMENSION,
+            max_height: DEFAULT_MAX_DIMENSION,
+        }
+    }
+}
+
+impl ImageValidation {
+    /// Checks the payload fetched from `url`: the type sniffed from its bytes must be an
+    /// (allowed) image type, and its header must decode to dimensions within bounds. Formats
+    /// whose headers cannot be read here, such as AVIF, are only checked by type.
+    pub(crate) fn check(&self, url: &str, bytes: &[u8]) -> Result<(), AppError> {
+        let invalid = |reason: String| AppError::InvalidImage {
+            url: url.to_string(),
+            reason,
+        };
+        let kind = match infer::get(bytes) {
+            Some(kind) if kind.matcher_type() == MatcherType::Image => kind,
+            Some(kind) => return Err(invalid(format!("content is {}", kind.mime_type()))),
+            None if looks_like_markup(bytes) => {
+                return Err(invalid("content is an HTML or XML document".to_string()));
+            }
+            None => return Err(invalid("content is of an unknown type".to_string())),
+        };
+        if !self.allowed_types.is_empty()
+            && !self.allowed_types.iter().any(|allowed| {
+                let allowed = mime_for_extension(allowed).unwrap_or(allowed);
+                allowed.eq_ignore_ascii_case(kind.mime_type())
+            })
+        {
+            return Err(invalid(format!(
+                "{} is not an allowed type",
+                kind.mime_type()
+            )));
+        }
+
+        let dimensions = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .map_err(|e| invalid(format!("header does not decode: {}", e)))?
+            .into_dimensions();
+        let (width, height) = match dimensions {
+            Ok(dimensions) => dimensions,
+            Err(ImageError::Unsupported(_)) => return Ok(()),
+            Err(e) => return Err(invalid(format!("header does not decode: {}", e))),
+        };
+        if width == 0 || height == 0 || width > self.max_width || height > self.max_height {
+            return Err(invalid(format!(
+                "dimensions {}x{} are out of bounds (at most {}x{})",
+                width, height, self.max_width, self.max_height
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `bytes` start like a markup document, e.g. `<?xml` or `<!DOCTYPE html>`.
+fn looks_like_markup(bytes: &[u8]) -> bool {
+    let start = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    start.trim_ascii_start().starts_with(b"<")
+}
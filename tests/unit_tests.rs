@@ -2,6 +2,11 @@ use base64::{Engine as _, engine::general_purpose};
 use mockito::Server as MockServer; // Using mockito for simpler HTTP mocking
 use serde_json::{Value, json};
 use std::error::Error;
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use std::time::{Duration, Instant, SystemTime};
 use std::{
     fs,
     path::{Path, PathBuf},
@@ -9,7 +14,15 @@ use std::{
 use tempfile::tempdir;
 
 // Import functionalities directly from the library
-use fcjp::{FileProcessResult, process_json_file};
+use fcjp::{
+    AppError, Deduplication, ExtractOptions, ExtractTarget, FetchFuture, FetchedImage, FieldStatus,
+    FileFetcher, FileProcessResult, FsSink, ImageNaming, ImageValidation, InlineOptions,
+    InlineOutcome, InputFilter, MemorySink, MirrorFetcher, OnCollision, OnTypeMismatch, OutputKind,
+    OutputSink, Processor, ProgressEvent, RateLimiter, Resize, RetryPolicy, ScreenshotFetcher,
+    ScreenshotTarget, Split, TarSink, Thumbnail, Transcode, TranscodeFormat, WriteOutput, ZipSink,
+    discover_input_files, extract_dir, extract_json_file, ignore_events, inline_value,
+    mirrored_output_dir, process_json_file, sanitize_filename, validate_json_pointer,
+};
 
 // Helper function to create test JSON files
 fn create_test_json_file(
@@ -75,7 +88,7 @@ fn create_json_with_null_screenshot(dir: &Path, filename: &str) -> Result<PathBu
 
 // Helper function to create test image data
 fn create_test_png_data() -> Vec<u8> {
-    // Simple PNG header + minimal data
+    // Smallest complete PNG: one transparent pixel
     vec![
         0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, // PNG signature
         0x00, 0x00, 0x00, 0x0D, // IHDR chunk length
@@ -88,24 +101,27 @@ fn create_test_png_data() -> Vec<u8> {
         0x00, // filter method
         0x00, // interlace method
         0x1F, 0x15, 0xC4, 0x89, // CRC
+        0x00, 0x00, 0x00, 0x0B, // IDAT chunk length
+        0x49, 0x44, 0x41, 0x54, // "IDAT"
+        0x78, 0xDA, 0x63, 0x60, 0x00, 0x02, 0x00, 0x00, 0x05, 0x00, 0x01, // zlib data
+        0xE9, 0xFA, 0xDC, 0xD8, // CRC
+        0x00, 0x00, 0x00, 0x00, // IEND chunk length
+        0x49, 0x45, 0x4E, 0x44, // "IEND"
+        0xAE, 0x42, 0x60, 0x82, // CRC
     ]
 }
 
 // Helper function to create test JPEG data
 fn create_test_jpg_data() -> Vec<u8> {
-    // Simple JPEG header + minimal data
-    vec![
-        0xFF, 0xD8, // SOI marker
-        0xFF, 0xE0, // APP0 marker
-        0x00, 0x10, // APP0 length
-        0x4A, 0x46, 0x49, 0x46, 0x00, // "JFIF\0"
-        0x01, 0x01, // version
-        0x00, // units
-        0x00, 0x01, // x density
-        0x00, 0x01, // y density
-        0x00, 0x00, // thumbnail
-        0xFF, 0xD9, // EOI marker
-    ]
+    // A complete JPEG needs quantization and Huffman tables, so let the encoder write one
+    let mut bytes = Vec::new();
+    image::RgbImage::new(1, 1)
+        .write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Jpeg,
+        )
+        .unwrap();
+    bytes
 }
 
 #[test]
@@ -144,7 +160,7 @@ fn test_process_json_file_success() {
 
     // Verify the result
     match result {
-        FileProcessResult::Success => {
+        FileProcessResult::Success(_) => {
             // Check if image was saved
             let image_file_path = image_dir.join("test_image.png");
             assert!(image_file_path.exists(), "Image file should exist");
@@ -314,8 +330,9 @@ fn test_process_json_file_server_error() {
     let result = process_json_file(&json_path, &image_dir, &base64_dir, &http_client, false);
 
     match result {
-        FileProcessResult::Failed(_, _) => {
+        FileProcessResult::Failed(_, AppError::Http { status, .. }) => {
             // Success - processing failed as expected for server error
+            assert_eq!(status.as_u16(), 404);
             mock.assert();
         }
         _ => panic!("Expected Failed for server error but got: {:?}", result),
@@ -356,7 +373,7 @@ fn test_process_json_file_different_image_types() {
         let result = process_json_file(&json_path, &image_dir, &base64_dir, &http_client, false);
 
         match result {
-            FileProcessResult::Success => {
+            FileProcessResult::Success(_) => {
                 // Check if image was saved
                 let image_file_path = image_dir.join("test_png.png");
                 assert!(image_file_path.exists(), "PNG image file should exist");
@@ -394,7 +411,7 @@ fn test_process_json_file_different_image_types() {
         let result = process_json_file(&json_path, &image_dir, &base64_dir, &http_client, false);
 
         match result {
-            FileProcessResult::Success => {
+            FileProcessResult::Success(_) => {
                 // Check if image was saved
                 let image_file_path = image_dir.join("test_jpg.jpg");
                 assert!(image_file_path.exists(), "JPEG image file should exist");
@@ -454,7 +471,7 @@ fn test_url_filename_extraction() {
 
     // Verify the result
     match result {
-        FileProcessResult::Success => {
+        FileProcessResult::Success(_) => {
             // Check that the filename was correctly extracted from the URL path
             let image_file_path = image_dir.join("complex_filename.png");
             assert!(
@@ -516,7 +533,7 @@ fn test_url_with_query_params() {
 
     // Verify the result
     match result {
-        FileProcessResult::Success => {
+        FileProcessResult::Success(_) => {
             // Check that the filename was correctly extracted from the URL (ignoring query params)
             let image_file_path = image_dir.join("image_with_params.png");
             assert!(
@@ -557,11 +574,11 @@ fn test_malformed_json() {
 
     // Verify the result is Failed
     match result {
-        FileProcessResult::Failed(_, error_msg) => {
+        FileProcessResult::Failed(_, error) => {
             assert!(
-                error_msg.contains("JSON Error") || error_msg.contains("Failed to parse JSON"),
+                matches!(error, AppError::Parse(_)),
                 "Error should indicate JSON parsing issue, but got: {}",
-                error_msg
+                error
             );
         }
         _ => panic!("Expected Failed for malformed JSON but got: {:?}", result),
@@ -606,7 +623,7 @@ fn test_non_utf8_filenames() {
 
     // Verify the result
     match result {
-        FileProcessResult::Success => {
+        FileProcessResult::Success(_) => {
             // Check if image was saved
             let image_file_path = image_dir.join("image.png");
             assert!(image_file_path.exists(), "Image file should exist");
@@ -623,3 +640,2712 @@ fn test_non_utf8_filenames() {
         _ => panic!("Expected Success but got: {:?}", result),
     }
 }
+
+#[test]
+fn test_discover_input_files_recursive_with_globs() {
+    let temp_dir = tempdir().unwrap();
+    let root = temp_dir.path();
+    let output_dir = root.join("base64");
+
+    fs::create_dir_all(root.join("example.com/blog")).unwrap();
+    fs::create_dir_all(root.join("other.org")).unwrap();
+    fs::create_dir_all(&output_dir).unwrap();
+
+    fs::write(root.join("top.json"), "{}").unwrap();
+    fs::write(root.join("example.com/index.json"), "{}").unwrap();
+    fs::write(root.join("example.com/blog/post.json"), "{}").unwrap();
+    fs::write(root.join("example.com/blog/draft.json"), "{}").unwrap();
+    fs::write(root.join("other.org/index.json"), "{}").unwrap();
+    fs::write(root.join("other.org/pages.jsonl"), "").unwrap();
+    fs::write(root.join("other.org/notes.txt"), "").unwrap();
+    fs::write(output_dir.join("top.json"), "{}").unwrap();
+
+    // Default include: every .json file, except those in the skipped output directory
+    let filter = InputFilter::new(&[], &[]).unwrap().skip_dir(&output_dir);
+    let files = discover_input_files(root, &filter).unwrap();
+    let relative: Vec<_> = files
+        .iter()
+        .map(|p| p.strip_prefix(root).unwrap().to_path_buf())
+        .collect();
+    assert_eq!(
+        relative,
+        vec![
+            PathBuf::from("example.com/blog/draft.json"),
+            PathBuf::from("example.com/blog/post.json"),
+            PathBuf::from("example.com/index.json"),
+            PathBuf::from("other.org/index.json"),
+            PathBuf::from("other.org/pages.jsonl"),
+            PathBuf::from("top.json"),
+        ]
+    );
+
+    // Include and exclude patterns are applied to the relative path
+    let filter = InputFilter::new(
+        &["example.com/**/*.json".to_string()],
+        &["**/draft.json".to_string()],
+    )
+    .unwrap();
+    let files = discover_input_files(root, &filter).unwrap();
+    assert_eq!(
+        files,
+        vec![
+            root.join("example.com/blog/post.json"),
+            root.join("example.com/index.json"),
+        ]
+    );
+
+    // Invalid patterns are reported instead of silently ignored
+    assert!(InputFilter::new(&["[".to_string()], &[]).is_err());
+}
+
+#[test]
+fn test_mirrored_output_avoids_name_collisions() {
+    let temp_dir = tempdir().unwrap();
+    let root = temp_dir.path().join("input");
+    let image_root = temp_dir.path().join("images");
+    let base64_root = temp_dir.path().join("base64");
+
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    let mock = server
+        .mock(
+            "GET",
+            mockito::Matcher::Regex(r"^/(a|b)/shot\.png$".to_string()),
+        )
+        .with_status(200)
+        .with_header("content-type", "image/png")
+        .with_body(&test_image)
+        .expect(2)
+        .create();
+
+    let http_client = reqwest::blocking::Client::new();
+    for shard in ["a", "b"] {
+        let shard_dir = root.join(shard);
+        fs::create_dir_all(&shard_dir).unwrap();
+        let image_url = format!("{}/{}/shot.png", server.url(), shard);
+        let json_path = create_test_json_file(&shard_dir, "index.json", &image_url).unwrap();
+
+        let image_dir = mirrored_output_dir(&root, &json_path, &image_root);
+        let base64_dir = mirrored_output_dir(&root, &json_path, &base64_root);
+        assert_eq!(image_dir, image_root.join(shard));
+        fs::create_dir_all(&image_dir).unwrap();
+        fs::create_dir_all(&base64_dir).unwrap();
+
+        let result = process_json_file(&json_path, &image_dir, &base64_dir, &http_client, false);
+        assert!(
+            matches!(result, FileProcessResult::Success(_)),
+            "{:?}",
+            result
+        );
+    }
+
+    assert!(image_root.join("a/shot.png").exists());
+    assert!(image_root.join("b/shot.png").exists());
+    assert!(base64_root.join("a/index.json").exists());
+    assert!(base64_root.join("b/index.json").exists());
+    mock.assert();
+}
+
+#[test]
+fn test_nested_screenshot_fields_via_json_pointer() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    let ok_mock = server
+        .mock("GET", "/nested.png")
+        .with_status(200)
+        .with_body(&test_image)
+        .create();
+    let missing_mock = server.mock("GET", "/expired.png").with_status(404).create();
+
+    let json_path = input_dir.join("nested.json");
+    let json_content = json!({
+        "data": { "screenshot": format!("{}/nested.png", server.url()) },
+        "metadata": { "og/image": format!("{}/expired.png", server.url()) },
+        "screenshot": "left alone"
+    });
+    fs::write(&json_path, json_content.to_string()).unwrap();
+
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .fields(["/data/screenshot", "/metadata/og~1image", "/does/not/exist"])
+        .build()
+        .unwrap();
+    let result = processor.process_file(&json_path);
+
+    // Absent fields are not reported; present ones are reported one by one
+    let reports = match result {
+        FileProcessResult::Success(reports) => reports,
+        _ => panic!("Expected Success but got: {:?}", result),
+    };
+    assert_eq!(reports.len(), 2);
+    assert_eq!(reports[0].pointer, "/data/screenshot");
+    assert!(matches!(reports[0].status, FieldStatus::Inlined { .. }));
+    assert_eq!(reports[1].pointer, "/metadata/og~1image");
+    assert!(matches!(reports[1].status, FieldStatus::Failed(_)));
+
+    let output: Value =
+        serde_json::from_str(&fs::read_to_string(base64_dir.join("nested.json")).unwrap()).unwrap();
+    assert!(
+        output["data"]["screenshot"]
+            .as_str()
+            .unwrap()
+            .starts_with("data:image/png;base64,")
+    );
+    // The failed field keeps its original URL, unrelated fields are untouched
+    assert!(
+        output["metadata"]["og/image"]
+            .as_str()
+            .unwrap()
+            .ends_with("/expired.png")
+    );
+    assert_eq!(output["screenshot"], "left alone");
+
+    ok_mock.assert();
+    missing_mock.assert();
+}
+
+#[test]
+fn test_validate_json_pointer() {
+    assert!(validate_json_pointer("").is_ok());
+    assert!(validate_json_pointer("/screenshot").is_ok());
+    assert!(validate_json_pointer("/a~0b/c~1d/0").is_ok());
+    assert!(validate_json_pointer("screenshot").is_err());
+    assert!(validate_json_pointer("/bad~2escape").is_err());
+    assert!(validate_json_pointer("/trailing~").is_err());
+}
+
+// Helper function to create a Firecrawl crawl/batch status envelope with one page per URL
+fn create_crawl_envelope_file(
+    dir: &Path,
+    filename: &str,
+    screenshot_urls: &[&str],
+) -> Result<PathBuf, Box<dyn Error>> {
+    let json_path = dir.join(filename);
+    let pages: Vec<Value> = screenshot_urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| {
+            json!({
+                "markdown": format!("# Page {}", i),
+                "screenshot": url,
+                "metadata": { "sourceURL": format!("https://example.com/{}", i) }
+            })
+        })
+        .collect();
+    let json_content = json!({
+        "success": true,
+        "status": "completed",
+        "total": pages.len(),
+        "data": pages
+    });
+
+    fs::write(&json_path, json_content.to_string())?;
+    Ok(json_path)
+}
+
+#[test]
+fn test_crawl_envelope_pages_are_inlined() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    let mock = server
+        .mock(
+            "GET",
+            mockito::Matcher::Regex(r"^/page[0-9]\.png$".to_string()),
+        )
+        .with_status(200)
+        .with_body(&test_image)
+        .expect(3)
+        .create();
+
+    let urls: Vec<String> = (0..3)
+        .map(|i| format!("{}/page{}.png", server.url(), i))
+        .collect();
+    let mut url_refs: Vec<&str> = urls.iter().map(String::as_str).collect();
+    url_refs.push(""); // a page without a screenshot
+    let json_path = create_crawl_envelope_file(&input_dir, "crawl.json", &url_refs).unwrap();
+
+    let http_client = reqwest::blocking::Client::new();
+    let result = process_json_file(&json_path, &image_dir, &base64_dir, &http_client, false);
+
+    let reports = match result {
+        FileProcessResult::Success(reports) => reports,
+        _ => panic!("Expected Success but got: {:?}", result),
+    };
+    let pointers: Vec<&str> = reports.iter().map(|r| r.pointer.as_str()).collect();
+    assert_eq!(
+        pointers,
+        vec![
+            "/data/0/screenshot",
+            "/data/1/screenshot",
+            "/data/2/screenshot"
+        ]
+    );
+
+    let output: Value =
+        serde_json::from_str(&fs::read_to_string(base64_dir.join("crawl.json")).unwrap()).unwrap();
+    assert_eq!(output["status"], "completed");
+    let pages = output["data"].as_array().unwrap();
+    assert_eq!(pages.len(), 4);
+    for page in &pages[..3] {
+        assert!(
+            page["screenshot"]
+                .as_str()
+                .unwrap()
+                .starts_with("data:image/png;base64,")
+        );
+    }
+    assert_eq!(pages[3]["screenshot"], "");
+    for i in 0..3 {
+        assert!(image_dir.join(format!("page{}.png", i)).exists());
+    }
+    mock.assert();
+}
+
+#[test]
+fn test_crawl_envelope_explode() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    let mock = server
+        .mock("GET", "/shot.png")
+        .with_status(200)
+        .with_body(&test_image)
+        .expect(2)
+        .create();
+
+    let url = format!("{}/shot.png", server.url());
+    let json_path = create_crawl_envelope_file(&input_dir, "batch.json", &[&url, &url]).unwrap();
+
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .explode_envelopes(true)
+        .build()
+        .unwrap();
+    let result = processor.process_file(&json_path);
+    assert!(
+        matches!(result, FileProcessResult::Success(_)),
+        "{:?}",
+        result
+    );
+
+    assert!(!base64_dir.join("batch.json").exists());
+    for i in 0..2 {
+        let page: Value = serde_json::from_str(
+            &fs::read_to_string(base64_dir.join(format!("batch.page-{}.json", i))).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(page["markdown"], format!("# Page {}", i));
+        assert!(
+            page["screenshot"]
+                .as_str()
+                .unwrap()
+                .starts_with("data:image/png;base64,")
+        );
+    }
+    mock.assert();
+}
+
+#[test]
+fn test_exploded_pages_do_not_collide_with_input_files() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+    fs::create_dir_all(&input_dir).unwrap();
+
+    let fixture = input_dir.join("shot.png");
+    fs::write(&fixture, create_test_png_data()).unwrap();
+    let url = format!("file://{}", fixture.display());
+    create_crawl_envelope_file(&input_dir, "crawl.json", &[&url, &url]).unwrap();
+    // A real input file with the name a page of the envelope used to get
+    create_test_json_file(&input_dir, "crawl-1.json", &url).unwrap();
+
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .fetcher(Arc::new(FileFetcher))
+        .explode_envelopes(true)
+        .build()
+        .unwrap();
+    let results = processor.process_dir(&input_dir).unwrap();
+    assert!(
+        results
+            .iter()
+            .all(|(_, result)| matches!(result, FileProcessResult::Success(_))),
+        "{:?}",
+        results
+    );
+
+    for i in 0..2 {
+        let page: Value = serde_json::from_str(
+            &fs::read_to_string(base64_dir.join(format!("crawl.page-{}.json", i))).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(page["markdown"], format!("# Page {}", i));
+    }
+    let standalone: Value =
+        serde_json::from_str(&fs::read_to_string(base64_dir.join("crawl-1.json")).unwrap())
+            .unwrap();
+    assert_eq!(standalone["title"], "Test Title");
+    assert!(
+        standalone["screenshot"]
+            .as_str()
+            .unwrap()
+            .starts_with("data:image/png;base64,")
+    );
+}
+
+#[test]
+fn test_jsonl_lines_processed_in_order() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    let mock = server
+        .mock(
+            "GET",
+            mockito::Matcher::Regex(r"^/line[0-9]\.png$".to_string()),
+        )
+        .with_status(200)
+        .with_body(&test_image)
+        .expect(2)
+        .create();
+
+    let lines = [
+        json!({ "id": 1, "screenshot": format!("{}/line1.png", server.url()) }).to_string(),
+        r#"{ "id": 2, "screenshot": "#.to_string(), // truncated line
+        json!({ "id": 3 }).to_string(),
+        json!({ "id": 4, "screenshot": format!("{}/line4.png", server.url()) }).to_string(),
+    ];
+    let jsonl_path = input_dir.join("pages.jsonl");
+    fs::write(&jsonl_path, lines.join("\n")).unwrap();
+
+    let http_client = reqwest::blocking::Client::new();
+    let result = process_json_file(&jsonl_path, &image_dir, &base64_dir, &http_client, false);
+
+    let reports = match result {
+        FileProcessResult::Success(reports) => reports,
+        _ => panic!("Expected Success but got: {:?}", result),
+    };
+    assert_eq!(reports.len(), 3);
+    assert_eq!(reports[0].line, Some(1));
+    assert!(matches!(reports[0].status, FieldStatus::Inlined { .. }));
+    assert_eq!(reports[1].line, Some(2));
+    assert!(matches!(
+        &reports[1].status,
+        FieldStatus::Failed(AppError::Parse(_))
+    ));
+    assert_eq!(reports[2].line, Some(4));
+    assert!(matches!(reports[2].status, FieldStatus::Inlined { .. }));
+
+    let output = fs::read_to_string(base64_dir.join("pages.jsonl")).unwrap();
+    let output_lines: Vec<&str> = output.lines().collect();
+    assert_eq!(output_lines.len(), 4);
+    let first: Value = serde_json::from_str(output_lines[0]).unwrap();
+    assert_eq!(first["id"], 1);
+    assert!(
+        first["screenshot"]
+            .as_str()
+            .unwrap()
+            .starts_with("data:image/png;base64,")
+    );
+    // The bad line is kept verbatim so the line order is preserved
+    assert_eq!(output_lines[1], lines[1]);
+    assert_eq!(
+        serde_json::from_str::<Value>(output_lines[2]).unwrap()["id"],
+        3
+    );
+    let fourth: Value = serde_json::from_str(output_lines[3]).unwrap();
+    assert_eq!(fourth["id"], 4);
+    assert!(
+        fourth["screenshot"]
+            .as_str()
+            .unwrap()
+            .starts_with("data:image/png;base64,")
+    );
+    mock.assert();
+}
+
+#[test]
+fn test_actions_screenshots_array() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+
+    let png_image = create_test_png_data();
+    let jpg_image = create_test_jpg_data();
+    let mut server = MockServer::new();
+    let main_mock = server
+        .mock("GET", "/main.png")
+        .with_status(200)
+        .with_body(&png_image)
+        .create();
+    let first_mock = server
+        .mock("GET", "/actions/first.png")
+        .with_status(200)
+        .with_body(&png_image)
+        .create();
+    let expired_mock = server
+        .mock("GET", "/actions/expired.png")
+        .with_status(403)
+        .create();
+    let third_mock = server
+        .mock("GET", "/actions/third.jpg")
+        .with_status(200)
+        .with_body(&jpg_image)
+        .create();
+
+    let json_path = input_dir.join("page.json");
+    let json_content = json!({
+        "screenshot": format!("{}/main.png", server.url()),
+        "actions": {
+            "screenshots": [
+                format!("{}/actions/first.png", server.url()),
+                format!("{}/actions/expired.png", server.url()),
+                format!("{}/actions/third.jpg", server.url())
+            ]
+        }
+    });
+    fs::write(&json_path, json_content.to_string()).unwrap();
+
+    let http_client = reqwest::blocking::Client::new();
+    let result = process_json_file(&json_path, &image_dir, &base64_dir, &http_client, false);
+
+    let reports = match result {
+        FileProcessResult::Success(reports) => reports,
+        _ => panic!("Expected Success but got: {:?}", result),
+    };
+    let pointers: Vec<&str> = reports.iter().map(|r| r.pointer.as_str()).collect();
+    assert_eq!(
+        pointers,
+        vec![
+            "/screenshot",
+            "/actions/screenshots/0",
+            "/actions/screenshots/1",
+            "/actions/screenshots/2"
+        ]
+    );
+    // The partial failure is reported without failing the whole file
+    assert!(matches!(reports[2].status, FieldStatus::Failed(_)));
+
+    // Array entries are saved under index-based names
+    assert!(image_dir.join("main.png").exists());
+    assert!(image_dir.join("page-actions-screenshots-0.png").exists());
+    assert!(!image_dir.join("page-actions-screenshots-1.png").exists());
+    assert!(image_dir.join("page-actions-screenshots-2.jpg").exists());
+
+    let output: Value =
+        serde_json::from_str(&fs::read_to_string(base64_dir.join("page.json")).unwrap()).unwrap();
+    let captures = output["actions"]["screenshots"].as_array().unwrap();
+    assert!(
+        captures[0]
+            .as_str()
+            .unwrap()
+            .starts_with("data:image/png;base64,")
+    );
+    assert!(
+        captures[1]
+            .as_str()
+            .unwrap()
+            .ends_with("/actions/expired.png")
+    );
+    assert!(
+        captures[2]
+            .as_str()
+            .unwrap()
+            .starts_with("data:image/jpeg;base64,")
+    );
+
+    main_mock.assert();
+    first_mock.assert();
+    expired_mock.assert();
+    third_mock.assert();
+}
+
+#[test]
+fn test_extract_round_trip() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+    let extracted_image_dir = temp_dir.path().join("extracted_images");
+    let extracted_dir = temp_dir.path().join("extracted");
+
+    for dir in [
+        &input_dir,
+        &image_dir,
+        &base64_dir,
+        &extracted_image_dir,
+        &extracted_dir,
+    ] {
+        fs::create_dir_all(dir).unwrap();
+    }
+
+    let test_image = create_test_jpg_data();
+    let mut server = MockServer::new();
+    let mock = server
+        .mock("GET", "/shot.jpg")
+        .with_status(200)
+        .with_body(&test_image)
+        .create();
+
+    let image_url = format!("{}/shot.jpg", server.url());
+    let json_path = create_test_json_file(&input_dir, "page.json", &image_url).unwrap();
+    let http_client = reqwest::blocking::Client::new();
+    let result = process_json_file(&json_path, &image_dir, &base64_dir, &http_client, false);
+    assert!(
+        matches!(result, FileProcessResult::Success(_)),
+        "{:?}",
+        result
+    );
+    mock.assert();
+
+    // Extract the inlined JSON back into an image file plus a relative reference
+    let inlined_path = base64_dir.join("page.json");
+    let result = extract_json_file(
+        &inlined_path,
+        &extracted_image_dir,
+        &extracted_dir,
+        &ExtractOptions::default(),
+        &ignore_events,
+    );
+    let reports = match result {
+        FileProcessResult::Success(reports) => reports,
+        _ => panic!("Expected Success but got: {:?}", result),
+    };
+    assert_eq!(reports.len(), 1);
+    assert!(matches!(reports[0].status, FieldStatus::Extracted { .. }));
+
+    // The MIME type decides the extension
+    let extracted_image = extracted_image_dir.join("page.jpg");
+    assert_eq!(fs::read(&extracted_image).unwrap(), test_image);
+    let slim: Value =
+        serde_json::from_str(&fs::read_to_string(extracted_dir.join("page.json")).unwrap())
+            .unwrap();
+    assert_eq!(slim["screenshot"], "../extracted_images/page.jpg");
+    assert_eq!(slim["title"], "Test Title");
+
+    // A URL template replaces the relative path
+    let options = ExtractOptions {
+        target: ExtractTarget::UrlTemplate("https://cdn.example/shots/{filename}".to_string()),
+        ..Default::default()
+    };
+    let result = extract_json_file(
+        &inlined_path,
+        &extracted_image_dir,
+        &extracted_dir,
+        &options,
+        &ignore_events,
+    );
+    assert!(
+        matches!(result, FileProcessResult::Success(_)),
+        "{:?}",
+        result
+    );
+    let slim: Value =
+        serde_json::from_str(&fs::read_to_string(extracted_dir.join("page.json")).unwrap())
+            .unwrap();
+    assert_eq!(slim["screenshot"], "https://cdn.example/shots/page.jpg");
+}
+
+#[test]
+fn test_extract_skips_plain_urls_and_rejects_bad_data_urls() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let extracted_dir = temp_dir.path().join("extracted");
+    for dir in [&input_dir, &image_dir, &extracted_dir] {
+        fs::create_dir_all(dir).unwrap();
+    }
+
+    // A remote URL is not a data URL, so there is nothing to extract
+    let json_path =
+        create_test_json_file(&input_dir, "remote.json", "https://example.com/a.png").unwrap();
+    let result = extract_json_file(
+        &json_path,
+        &image_dir,
+        &extracted_dir,
+        &ExtractOptions::default(),
+        &ignore_events,
+    );
+    assert!(
+        matches!(result, FileProcessResult::Skipped(_)),
+        "{:?}",
+        result
+    );
+
+    // A data URL that is not base64 encoded cannot be decoded into an image
+    let json_path =
+        create_test_json_file(&input_dir, "plain.json", "data:text/plain,hello").unwrap();
+    let result = extract_json_file(
+        &json_path,
+        &image_dir,
+        &extracted_dir,
+        &ExtractOptions::default(),
+        &ignore_events,
+    );
+    match result {
+        FileProcessResult::Failed(_, error @ AppError::InvalidDataUrl { .. }) => {
+            assert!(error.to_string().contains("not base64"), "{}", error)
+        }
+        _ => panic!("Expected Failed but got: {:?}", result),
+    }
+}
+
+#[test]
+fn test_extract_writes_through_the_configured_sink() {
+    let temp_dir = tempdir().unwrap();
+    let image_dir = temp_dir.path().join("images");
+    let extracted_dir = temp_dir.path().join("extracted");
+    let test_image = create_test_png_data();
+    let data_url = format!(
+        "data:image/png;base64,{}",
+        general_purpose::STANDARD.encode(&test_image)
+    );
+    let json_path = create_test_json_file(temp_dir.path(), "page.json", &data_url).unwrap();
+
+    let sink = Arc::new(MemorySink::new());
+    let options = ExtractOptions {
+        sink: sink.clone(),
+        ..Default::default()
+    };
+    let result = extract_json_file(
+        &json_path,
+        &image_dir,
+        &extracted_dir,
+        &options,
+        &ignore_events,
+    );
+    assert!(
+        matches!(result, FileProcessResult::Success(_)),
+        "{:?}",
+        result
+    );
+
+    assert_eq!(sink.paths(OutputKind::Image), [image_dir.join("page.png")]);
+    assert_eq!(sink.get(&image_dir.join("page.png")).unwrap(), test_image);
+    assert_eq!(
+        sink.paths(OutputKind::Json),
+        [extracted_dir.join("page.json")]
+    );
+    let slim: Value =
+        serde_json::from_slice(&sink.get(&extracted_dir.join("page.json")).unwrap()).unwrap();
+    assert_eq!(slim["screenshot"], "../images/page.png");
+    assert!(!image_dir.exists() && !extracted_dir.exists());
+}
+
+#[test]
+fn test_transient_errors_are_retried() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    // Two throttled responses, then the image
+    let throttled_mock = server
+        .mock("GET", "/flaky.png")
+        .with_status(429)
+        .with_header("retry-after", "0")
+        .expect(2)
+        .create();
+    let ok_mock = server
+        .mock("GET", "/flaky.png")
+        .with_status(200)
+        .with_body(&test_image)
+        .create();
+
+    let image_url = format!("{}/flaky.png", server.url());
+    let json_path = create_test_json_file(&input_dir, "flaky.json", &image_url).unwrap();
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .retry(RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let result = processor.process_file(&json_path);
+
+    let reports = match result {
+        FileProcessResult::Success(reports) => reports,
+        _ => panic!("Expected Success but got: {:?}", result),
+    };
+    assert_eq!(reports[0].attempts, 3);
+    throttled_mock.assert();
+    ok_mock.assert();
+}
+
+#[test]
+fn test_permanent_errors_are_not_retried() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+
+    let mut server = MockServer::new();
+    let not_found_mock = server
+        .mock("GET", "/gone.png")
+        .with_status(404)
+        .expect(1)
+        .create();
+    let unavailable_mock = server
+        .mock("GET", "/down.png")
+        .with_status(503)
+        .expect(3)
+        .create();
+
+    let json_path = input_dir.join("mixed.json");
+    let json_content = json!({
+        "screenshot": format!("{}/gone.png", server.url()),
+        "actions": { "screenshots": [format!("{}/down.png", server.url())] }
+    });
+    fs::write(&json_path, json_content.to_string()).unwrap();
+
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .retry(RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let result = processor.process_file(&json_path);
+
+    match result {
+        FileProcessResult::Failed(_, AppError::Http { status, .. }) => {
+            assert_eq!(status.as_u16(), 404);
+        }
+        _ => panic!("Expected Failed but got: {:?}", result),
+    }
+    not_found_mock.assert();
+    unavailable_mock.assert();
+}
+
+#[test]
+fn test_retry_backoff_and_retry_after() {
+    let policy = RetryPolicy {
+        max_retries: 5,
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_millis(350),
+    };
+    for _ in 0..20 {
+        let first = policy.backoff(1);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        let third = policy.backoff(3);
+        assert!(third >= Duration::from_millis(175) && third <= Duration::from_millis(350));
+    }
+
+    assert_eq!(
+        fcjp::fetch::parse_retry_after("7"),
+        Some(Duration::from_secs(7))
+    );
+    assert_eq!(
+        fcjp::fetch::parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(fcjp::fetch::parse_retry_after("soon"), None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_per_host_rate_limit_spaces_requests() {
+    let temp_dir = tempdir().unwrap();
+    let urls: Vec<String> = (0..8)
+        .map(|i| format!("https://shots.example/shot{}.png", i))
+        .collect();
+    let mut document = json!({ "actions": { "screenshots": urls } });
+
+    // A burst of 5, then one request every 200ms. Time is paused, so the waits are measured
+    // on the runtime's clock and do not depend on how fast the machine is.
+    let processor = Processor::builder(temp_dir.path().join("images"), temp_dir.path())
+        .fetcher(Arc::new(FixtureFetcher {
+            bytes: create_test_png_data(),
+            requested: Default::default(),
+        }))
+        .rate_limiter(Arc::new(RateLimiter::new(None, Some(5.0))))
+        .build()
+        .unwrap();
+    let started = tokio::time::Instant::now();
+    let reports = processor
+        .process_value_async(&mut document, Path::new("actions.json"))
+        .await;
+    let elapsed = started.elapsed();
+
+    assert_eq!(reports.len(), 8);
+    assert!(
+        reports
+            .iter()
+            .all(|report| matches!(report.status, FieldStatus::Inlined { .. }))
+    );
+    assert!(
+        elapsed >= Duration::from_millis(600) && elapsed < Duration::from_millis(700),
+        "8 requests at 5 rps finished in {:?}",
+        elapsed
+    );
+}
+
+#[test]
+fn test_rate_limits_are_global_and_per_host() {
+    let start = Instant::now();
+    let at = |millis: u64| start + Duration::from_millis(millis);
+
+    let per_host = RateLimiter::new(None, Some(5.0));
+    for _ in 0..5 {
+        assert_eq!(
+            per_host.reserve("https://a.example/shot.png", at(0)),
+            Duration::ZERO
+        );
+        // Each host has its own bucket, so both bursts go through at once
+        assert_eq!(
+            per_host.reserve("https://B.example/shot.png", at(0)),
+            Duration::ZERO
+        );
+    }
+    // Host names are case-insensitive; the sixth request waits for the next token
+    assert_eq!(
+        per_host.reserve("https://b.example/another.png", at(0)),
+        Duration::from_millis(200)
+    );
+    assert_eq!(
+        per_host.reserve("https://b.example/another.png", at(100)),
+        Duration::from_millis(300)
+    );
+
+    let global = RateLimiter::new(Some(5.0), None);
+    let delays: Vec<Duration> = (0..7)
+        .map(|i| global.reserve(&format!("https://host{}.example/shot.png", i), at(0)))
+        .collect();
+    assert_eq!(delays[..5], [Duration::ZERO; 5]);
+    assert_eq!(
+        delays[5..],
+        [Duration::from_millis(200), Duration::from_millis(400)]
+    );
+    // Tokens refill over time, up to a burst of one second's worth
+    let global = RateLimiter::new(Some(5.0), None);
+    for _ in 0..6 {
+        global.reserve("https://host.example/shot.png", at(0));
+    }
+    assert_eq!(global.reserve("not a url", at(1200)), Duration::ZERO);
+
+    let unlimited = RateLimiter::default();
+    for _ in 0..100 {
+        assert_eq!(unlimited.reserve("not a url", at(0)), Duration::ZERO);
+    }
+}
+
+#[test]
+fn test_incremental_mode_skips_up_to_date_outputs() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    let mock = server
+        .mock("GET", "/image.png")
+        .with_status(200)
+        .with_body(&test_image)
+        .expect(2)
+        .create();
+
+    let image_url = format!("{}/image.png", server.url());
+    let json_path = create_test_json_file(&input_dir, "page.json", &image_url).unwrap();
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .incremental(true)
+        .build()
+        .unwrap();
+    let process = || processor.process_file(&json_path);
+
+    assert!(matches!(process(), FileProcessResult::Success(_)));
+    // The output is newer than the input now
+    match process() {
+        FileProcessResult::UpToDate(reason) => assert!(reason.contains("up to date")),
+        other => panic!("Expected UpToDate but got: {:?}", other),
+    }
+
+    // Touching the input makes it stale again
+    fs::File::options()
+        .write(true)
+        .open(&json_path)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(3600))
+        .unwrap();
+    assert!(matches!(process(), FileProcessResult::Success(_)));
+    mock.assert();
+}
+
+#[test]
+fn test_already_inlined_screenshots_are_up_to_date() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+
+    let data_url = "data:image/png;base64,iVBORw0KGgo=";
+    let json_path = create_test_json_file(&input_dir, "inlined.json", data_url).unwrap();
+    let http_client = reqwest::blocking::Client::new();
+    let result = process_json_file(&json_path, &image_dir, &base64_dir, &http_client, false);
+    assert!(
+        matches!(result, FileProcessResult::UpToDate(_)),
+        "Expected UpToDate but got: {:?}",
+        result
+    );
+    assert!(!base64_dir.join("inlined.json").exists());
+
+    // Mixed with a fresh URL, only the URL is downloaded
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    let mock = server
+        .mock("GET", "/fresh.png")
+        .with_status(200)
+        .with_body(&test_image)
+        .expect(1)
+        .create();
+    let mixed_path = input_dir.join("mixed.json");
+    let json_content = json!({
+        "screenshot": data_url,
+        "actions": { "screenshots": [format!("{}/fresh.png", server.url())] }
+    });
+    fs::write(&mixed_path, json_content.to_string()).unwrap();
+
+    let reports = match process_json_file(&mixed_path, &image_dir, &base64_dir, &http_client, false)
+    {
+        FileProcessResult::Success(reports) => reports,
+        other => panic!("Expected Success but got: {:?}", other),
+    };
+    assert!(matches!(reports[0].status, FieldStatus::UpToDate));
+    assert!(matches!(reports[1].status, FieldStatus::Inlined { .. }));
+    let output: Value =
+        serde_json::from_str(&fs::read_to_string(base64_dir.join("mixed.json")).unwrap()).unwrap();
+    assert_eq!(output["screenshot"], data_url);
+    mock.assert();
+}
+
+#[test]
+fn test_typed_errors_keep_kind_and_source() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+
+    let mut server = MockServer::new();
+    let mock = server
+        .mock("GET", "/throttled.png")
+        .with_status(429)
+        .create();
+    let http_client = reqwest::blocking::Client::new();
+
+    // The failing line of a JSON Lines file wraps the underlying error
+    let jsonl_path = input_dir.join("pages.jsonl");
+    fs::write(
+        &jsonl_path,
+        format!(
+            "{}\n",
+            json!({ "screenshot": format!("{}/throttled.png", server.url()) })
+        ),
+    )
+    .unwrap();
+    let result = process_json_file(&jsonl_path, &image_dir, &base64_dir, &http_client, false);
+    let error = match result {
+        FileProcessResult::Failed(_, error) => error,
+        other => panic!("Expected Failed but got: {:?}", other),
+    };
+    match &error {
+        AppError::Line { line, source } => {
+            assert_eq!(*line, 1);
+            assert!(
+                matches!(source.as_ref(), AppError::Http { status, .. } if status.as_u16() == 429)
+            );
+        }
+        other => panic!("Expected a line error but got: {:?}", other),
+    }
+    assert!(error.is_transient());
+    assert!(error.source().is_some());
+    mock.assert();
+
+    // A document that is not an object cannot hold screenshot fields
+    let array_path = input_dir.join("array.json");
+    fs::write(&array_path, "[1, 2, 3]").unwrap();
+    let result = process_json_file(&array_path, &image_dir, &base64_dir, &http_client, false);
+    assert!(
+        matches!(result, FileProcessResult::Failed(_, AppError::NotObject)),
+        "Expected NotObject but got: {:?}",
+        result
+    );
+
+    // Missing input files surface as read errors with their io::Error
+    let missing_path = input_dir.join("missing.json");
+    match process_json_file(&missing_path, &image_dir, &base64_dir, &http_client, false) {
+        FileProcessResult::Failed(_, AppError::Read { path, source }) => {
+            assert_eq!(path, missing_path);
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        }
+        other => panic!("Expected a read error but got: {:?}", other),
+    }
+
+    assert!(matches!(
+        validate_json_pointer("screenshot"),
+        Err(AppError::InvalidPointer { .. })
+    ));
+}
+
+#[test]
+fn test_processor_process_dir_and_value() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    // Output directories inside the input tree must not be picked up as input
+    let image_dir = input_dir.join("images");
+    let base64_dir = input_dir.join("base64");
+
+    fs::create_dir_all(input_dir.join("a")).unwrap();
+    fs::create_dir_all(input_dir.join("b")).unwrap();
+
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    let mock = server
+        .mock("GET", "/image.png")
+        .with_status(200)
+        .with_body(&test_image)
+        .expect(4)
+        .create();
+    let image_url = format!("{}/image.png", server.url());
+    create_test_json_file(&input_dir.join("a"), "page.json", &image_url).unwrap();
+    create_test_json_file(&input_dir.join("b"), "page.json", &image_url).unwrap();
+
+    let processor = Processor::builder(&image_dir, &base64_dir).build().unwrap();
+    let results = processor.process_dir(&input_dir).unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].0.ends_with("a/page.json"));
+    assert!(results[1].0.ends_with("b/page.json"));
+    assert!(
+        results
+            .iter()
+            .all(|(_, result)| matches!(result, FileProcessResult::Success(_)))
+    );
+    assert!(base64_dir.join("a").join("page.json").exists());
+    assert!(base64_dir.join("b").join("page.json").exists());
+
+    // The same processor can be shared between threads
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+    assert_send_sync(&processor);
+    std::thread::scope(|scope| {
+        for name in ["first", "second"] {
+            let processor = &processor;
+            let image_url = &image_url;
+            scope.spawn(move || {
+                let mut document = json!({ "screenshot": image_url, "title": name });
+                let reports = processor.process_value(&mut document, Path::new(name));
+                assert_eq!(reports.len(), 1);
+                assert!(matches!(reports[0].status, FieldStatus::Inlined { .. }));
+                assert!(
+                    document["screenshot"]
+                        .as_str()
+                        .unwrap()
+                        .starts_with("data:image/png;base64,")
+                );
+                assert_eq!(document["title"], name);
+            });
+        }
+    });
+    mock.assert();
+}
+
+#[test]
+fn test_processor_config_is_validated() {
+    let result = Processor::builder("images", "base64")
+        .fields(["screenshot"])
+        .build();
+    assert!(matches!(result, Err(AppError::InvalidPointer { .. })));
+
+    let result = Processor::builder("images", "base64")
+        .include(vec!["[".to_string()])
+        .build();
+    assert!(matches!(result, Err(AppError::InvalidGlob(_))));
+}
+
+/// Serves fixed bytes for every URL and records what was asked for.
+struct FixtureFetcher {
+    bytes: Vec<u8>,
+    requested: std::sync::Mutex<Vec<String>>,
+}
+
+impl ScreenshotFetcher for FixtureFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        self.requested.lock().unwrap().push(url.to_string());
+        Box::pin(async move {
+            Ok(FetchedImage {
+                bytes: self.bytes.clone(),
+                content_type: None,
+            })
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_inline_value_in_memory() {
+    let test_image = create_test_png_data();
+    let fetcher = FixtureFetcher {
+        bytes: test_image.clone(),
+        requested: Default::default(),
+    };
+    let mut document = json!({
+        "screenshot": "https://example.com/shots/home.png",
+        "actions": { "screenshots": ["https://example.com/a/1", "data:image/png;base64,AAAA"] }
+    });
+
+    let fields = inline_value(
+        &mut document,
+        &fetcher,
+        &InlineOptions::default(),
+        Path::new("/nonexistent/page.json"),
+    )
+    .await;
+    assert_eq!(fields.len(), 3);
+    assert_eq!(
+        *fetcher.requested.lock().unwrap(),
+        [
+            "https://example.com/shots/home.png",
+            "https://example.com/a/1"
+        ]
+    );
+
+    let images: Vec<_> = fields
+        .iter()
+        .filter_map(|field| match &field.outcome {
+            InlineOutcome::Inlined(image) => Some(image),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(images.len(), 2);
+    assert_eq!(images[0].filename, "home.png");
+    assert_eq!(images[0].mime_type, "image/png");
+    assert_eq!(images[0].bytes, test_image);
+    assert_eq!(images[0].source_url, "https://example.com/shots/home.png");
+    assert_eq!(images[1].filename, "page-actions-screenshots-0.png");
+    assert!(matches!(fields[2].outcome, InlineOutcome::UpToDate));
+
+    let expected = format!(
+        "data:image/png;base64,{}",
+        general_purpose::STANDARD.encode(&test_image)
+    );
+    assert_eq!(document["screenshot"], expected);
+    assert_eq!(document["actions"]["screenshots"][0], expected);
+    assert_eq!(
+        document["actions"]["screenshots"][1],
+        "data:image/png;base64,AAAA"
+    );
+}
+
+#[tokio::test]
+async fn test_blocking_api_fails_inside_async_runtime() {
+    let temp_dir = tempdir().unwrap();
+    let json_path =
+        create_test_json_file(temp_dir.path(), "page.json", "file:///shot.png").unwrap();
+    let processor = Processor::builder(temp_dir.path().join("images"), temp_dir.path().join("out"))
+        .build()
+        .unwrap();
+
+    match processor.process_file(&json_path) {
+        FileProcessResult::Failed(_, AppError::AsyncContext) => {}
+        other => panic!("Expected an async context error, got {:?}", other),
+    }
+    assert!(matches!(
+        processor.process_dir(temp_dir.path()),
+        Err(AppError::AsyncContext)
+    ));
+    assert!(!temp_dir.path().join("out").exists());
+
+    // The async methods work here
+    let result = processor.process_file_async(&json_path).await;
+    assert!(
+        !matches!(result, FileProcessResult::Failed(_, AppError::AsyncContext)),
+        "Async method should not report an async context error"
+    );
+}
+
+#[test]
+fn test_file_and_mirror_fetchers() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+    let mirror_dir = temp_dir.path().join("mirror");
+    fs::create_dir_all(&input_dir).unwrap();
+    fs::create_dir_all(mirror_dir.join("cdn.example:8080/shots")).unwrap();
+
+    let test_image = create_test_png_data();
+    let local_image = temp_dir.path().join("local.png");
+    fs::write(&local_image, &test_image).unwrap();
+    fs::write(
+        mirror_dir.join("cdn.example:8080/shots/home.png"),
+        &test_image,
+    )
+    .unwrap();
+
+    // file:// URLs are read from disk
+    let file_url = url::Url::from_file_path(&local_image).unwrap().to_string();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let fetched = runtime.block_on(FileFetcher.fetch(&file_url)).unwrap();
+    assert_eq!(fetched.bytes, test_image);
+    assert!(matches!(
+        runtime.block_on(FileFetcher.fetch("https://example.com/a.png")),
+        Err(AppError::UnsupportedUrl { .. })
+    ));
+
+    // The mirror is keyed by host, port and path; the query string is ignored
+    let mirror = MirrorFetcher::new(&mirror_dir);
+    assert_eq!(
+        mirror
+            .mirror_path("https://cdn.example:8080/shots/../shots/home.png?token=abc")
+            .unwrap(),
+        mirror_dir.join("cdn.example:8080/shots/home.png")
+    );
+    assert_eq!(
+        mirror.mirror_path("https://cdn.example/").unwrap(),
+        mirror_dir.join("cdn.example/index.html")
+    );
+    match runtime.block_on(mirror.fetch("https://cdn.example/missing.png")) {
+        Err(AppError::Read { source, .. }) => {
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound)
+        }
+        other => panic!("Expected a read error but got: {:?}", other),
+    }
+
+    // A processor configured with the mirror never touches the network
+    let json_path = create_test_json_file(
+        &input_dir,
+        "page.json",
+        "https://cdn.example:8080/shots/home.png?token=abc",
+    )
+    .unwrap();
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .fetcher(Arc::new(mirror))
+        .build()
+        .unwrap();
+    fs::create_dir_all(&image_dir).unwrap();
+    fs::create_dir_all(&base64_dir).unwrap();
+    let result = processor.process_file(&json_path);
+    assert!(
+        matches!(result, FileProcessResult::Success(_)),
+        "Expected Success but got: {:?}",
+        result
+    );
+    assert_eq!(fs::read(image_dir.join("home.png")).unwrap(), test_image);
+}
+
+#[test]
+fn test_output_sinks() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let out_dir = temp_dir.path().join("out");
+    let image_dir = out_dir.join("images");
+    let base64_dir = out_dir.join("base64");
+    fs::create_dir_all(input_dir.join("nested")).unwrap();
+
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    let mock = server
+        .mock("GET", "/shot.png")
+        .with_status(200)
+        .with_body(&test_image)
+        .expect(3)
+        .create();
+    let image_url = format!("{}/shot.png", server.url());
+    create_test_json_file(&input_dir.join("nested"), "page.json", &image_url).unwrap();
+
+    // In memory: nothing is written to disk, not even the output directories
+    let memory = Arc::new(MemorySink::new());
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .output_sink(memory.clone())
+        .build()
+        .unwrap();
+    let results = processor.process_dir(&input_dir).unwrap();
+    assert!(matches!(results[0].1, FileProcessResult::Success(_)));
+    assert!(!out_dir.exists());
+    assert_eq!(
+        memory.paths(OutputKind::Image),
+        [image_dir.join("nested").join("shot.png")]
+    );
+    let json_paths = memory.paths(OutputKind::Json);
+    assert_eq!(json_paths, [base64_dir.join("nested").join("page.json")]);
+    let output: Value = serde_json::from_slice(&memory.get(&json_paths[0]).unwrap()).unwrap();
+    assert!(
+        output["screenshot"]
+            .as_str()
+            .unwrap()
+            .starts_with("data:image/png;base64,")
+    );
+
+    // A closure receives every output as it is produced
+    let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let callback = {
+        let received = Arc::clone(&received);
+        move |kind: OutputKind, path: &Path, _bytes: &[u8]| {
+            received.lock().unwrap().push((kind, path.to_path_buf()));
+            Ok(())
+        }
+    };
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .output_sink(Arc::new(callback))
+        .build()
+        .unwrap();
+    let json_path = input_dir.join("nested").join("page.json");
+    assert!(matches!(
+        processor.process_file(&json_path),
+        FileProcessResult::Success(_)
+    ));
+    assert_eq!(
+        *received.lock().unwrap(),
+        [
+            (OutputKind::Image, image_dir.join("shot.png")),
+            (OutputKind::Json, base64_dir.join("page.json")),
+        ]
+    );
+
+    // Archives name their entries relative to the base directory
+    let tar_sink = Arc::new(TarSink::new(Vec::new(), &out_dir));
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .output_sink(tar_sink.clone())
+        .build()
+        .unwrap();
+    processor.process_dir(&input_dir).unwrap();
+    let archive = tar_sink.finish().unwrap();
+    let mut entries: Vec<String> = tar::Archive::new(archive.as_slice())
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+        .collect();
+    entries.sort();
+    assert_eq!(
+        entries,
+        ["base64/nested/page.json", "images/nested/shot.png"]
+    );
+    assert!(!out_dir.exists());
+    mock.assert();
+
+    // Writes after finishing fail instead of producing a corrupt archive
+    assert!(matches!(
+        tar_sink.write(OutputKind::Json, &base64_dir.join("late.json"), b"{}"),
+        Err(AppError::Write { .. })
+    ));
+
+    let zip_sink = ZipSink::new(std::io::Cursor::new(Vec::new()), &out_dir);
+    zip_sink
+        .write(OutputKind::Image, &image_dir.join("a.png"), &test_image)
+        .unwrap();
+    let archive = zip_sink.finish().unwrap();
+    let mut zip = zip::ZipArchive::new(archive).unwrap();
+    let mut entry = zip.by_name("images/a.png").unwrap();
+    let mut bytes = Vec::new();
+    std::io::Read::read_to_end(&mut entry, &mut bytes).unwrap();
+    assert_eq!(bytes, test_image);
+}
+
+#[test]
+fn test_progress_events() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+    fs::create_dir_all(&input_dir).unwrap();
+
+    let test_image = create_test_png_data();
+    let mut server = MockServer::new();
+    let flaky = server
+        .mock("GET", "/shot.png")
+        .with_status(503)
+        .expect(1)
+        .create();
+    let image_url = format!("{}/shot.png", server.url());
+    let missing_url = format!("{}/missing.png", server.url());
+    let json_path = input_dir.join("page.json");
+    fs::write(
+        &json_path,
+        json!({ "screenshot": image_url, "actions": { "screenshots": [missing_url] } }).to_string(),
+    )
+    .unwrap();
+
+    // Events can be forwarded into a channel and consumed elsewhere
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .retry(RetryPolicy {
+            max_retries: 1,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        })
+        .on_event(Arc::new(move |event: &ProgressEvent| {
+            sender.send(event.clone()).unwrap();
+        }))
+        .build()
+        .unwrap();
+    let _ok = server
+        .mock("GET", "/shot.png")
+        .with_status(200)
+        .with_body(&test_image)
+        .create();
+    let _missing = server.mock("GET", "/missing.png").with_status(404).create();
+    assert!(matches!(
+        processor.process_file(&json_path),
+        FileProcessResult::Success(_)
+    ));
+    drop(processor);
+    flaky.assert();
+
+    let mut events: Vec<ProgressEvent> = receiver.iter().collect();
+    let file = json_path.clone();
+    // Fields are fetched concurrently, so the second field's download may come at any point
+    let missing_download = ProgressEvent::Downloading {
+        file: file.clone(),
+        pointer: "/actions/screenshots/0".to_string(),
+        url: missing_url.clone(),
+        attempt: 1,
+    };
+    let position = events.iter().position(|event| *event == missing_download);
+    events.remove(position.expect("missing download was not announced"));
+    // The 404 is reported as a warning for its field; the rest of the file is still written
+    let warning = events.remove(5);
+    assert!(
+        matches!(
+            &warning,
+            ProgressEvent::Warning { line: None, pointer, message, .. }
+                if pointer == "/actions/screenshots/0" && message.contains("404")
+        ),
+        "{:?}",
+        warning
+    );
+    assert_eq!(
+        events,
+        [
+            ProgressEvent::Started { file: file.clone() },
+            ProgressEvent::Downloading {
+                file: file.clone(),
+                pointer: "/screenshot".to_string(),
+                url: image_url.clone(),
+                attempt: 1,
+            },
+            ProgressEvent::Downloading {
+                file: file.clone(),
+                pointer: "/screenshot".to_string(),
+                url: image_url.clone(),
+                attempt: 2,
+            },
+            ProgressEvent::Downloaded {
+                file: file.clone(),
+                pointer: "/screenshot".to_string(),
+                bytes: test_image.len(),
+            },
+            ProgressEvent::Saved {
+                file: file.clone(),
+                kind: OutputKind::Image,
+                path: image_dir.join("shot.png"),
+            },
+            ProgressEvent::Saved {
+                file: file.clone(),
+                kind: OutputKind::Json,
+                path: base64_dir.join("page.json"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_dir_results_are_reported_as_files_finish() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+    fs::create_dir_all(input_dir.join("sub")).unwrap();
+
+    let fixture = temp_dir.path().join("shot.png");
+    fs::write(&fixture, create_test_png_data()).unwrap();
+    let url = format!("file://{}", fixture.display());
+    create_test_json_file(&input_dir, "a.json", &url).unwrap();
+    create_test_json_file(&input_dir.join("sub"), "b.json", &url).unwrap();
+    fs::write(input_dir.join("c.json"), r#"{"title":"No screenshot"}"#).unwrap();
+
+    let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let found = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .fetcher(Arc::new(FileFetcher))
+        .on_file_result({
+            let reported = Arc::clone(&reported);
+            Arc::new(move |file: &Path, result: &FileProcessResult| {
+                let success = matches!(result, FileProcessResult::Success(_));
+                reported.lock().unwrap().push((file.to_path_buf(), success));
+            })
+        })
+        .on_event({
+            let found = Arc::clone(&found);
+            Arc::new(move |event: &ProgressEvent| {
+                if let ProgressEvent::Found { files, .. } = event {
+                    found.lock().unwrap().push(*files);
+                }
+            })
+        })
+        .build()
+        .unwrap();
+    let results = processor.process_dir(&input_dir).unwrap();
+    assert_eq!(*found.lock().unwrap(), [3]);
+    let mut reported = reported.lock().unwrap().clone();
+    reported.sort();
+    let expected: Vec<(PathBuf, bool)> = results
+        .iter()
+        .map(|(file, result)| {
+            (
+                file.clone(),
+                matches!(result, FileProcessResult::Success(_)),
+            )
+        })
+        .collect();
+    assert_eq!(reported, expected);
+    assert_eq!(expected.iter().filter(|(_, success)| *success).count(), 2);
+
+    // Extraction walks and mirrors the tree the same way
+    let extracted = Arc::new(AtomicUsize::new(0));
+    let options = ExtractOptions {
+        concurrency: 2,
+        on_file_result: {
+            let extracted = Arc::clone(&extracted);
+            Arc::new(move |_: &Path, _: &FileProcessResult| {
+                extracted.fetch_add(1, Ordering::SeqCst);
+            })
+        },
+        ..Default::default()
+    };
+    let extract_dir_path = temp_dir.path().join("extracted");
+    let results = extract_dir(
+        &base64_dir,
+        &temp_dir.path().join("extracted-images"),
+        &extract_dir_path,
+        &options,
+        &ignore_events,
+    )
+    .unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(extracted.load(Ordering::SeqCst), 2);
+    let slim: Value = serde_json::from_str(
+        &fs::read_to_string(extract_dir_path.join("sub").join("b.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(slim["screenshot"], "../../extracted-images/sub/b.png");
+}
+
+/// Holds every fetch open for a moment and records how many were in flight at once.
+#[derive(Default)]
+struct SlowFetcher {
+    bytes: Vec<u8>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+impl ScreenshotFetcher for SlowFetcher {
+    fn fetch<'a>(&'a self, _url: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(FetchedImage {
+                bytes: self.bytes.clone(),
+                content_type: None,
+            })
+        })
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_async_processing_bounds_downloads() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    fs::create_dir_all(&input_dir).unwrap();
+    for name in ["a", "b", "c"] {
+        let urls: Vec<String> = (0..3)
+            .map(|index| format!("https://example.com/{}/{}.png", name, index))
+            .collect();
+        fs::write(
+            input_dir.join(format!("{}.json", name)),
+            json!({ "actions": { "screenshots": urls } }).to_string(),
+        )
+        .unwrap();
+    }
+
+    let fetcher = Arc::new(SlowFetcher {
+        bytes: create_test_png_data(),
+        ..Default::default()
+    });
+    let sink = Arc::new(MemorySink::new());
+    let processor = Processor::builder(
+        temp_dir.path().join("images"),
+        temp_dir.path().join("base64"),
+    )
+    .fetcher(fetcher.clone())
+    .output_sink(sink.clone())
+    .concurrency(2)
+    .build()
+    .unwrap();
+
+    // The async API can be driven from a spawned task
+    let results = tokio::spawn(async move { processor.process_dir_async(&input_dir).await })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(results.len(), 3);
+    for (path, result) in &results {
+        assert!(
+            matches!(result, FileProcessResult::Success(reports) if reports.len() == 3),
+            "{:?}: {:?}",
+            path,
+            result
+        );
+    }
+    assert_eq!(fetcher.max_in_flight.load(Ordering::SeqCst), 2);
+    assert_eq!(sink.paths(OutputKind::Image).len(), 9);
+}
+
+#[test]
+fn test_data_urls_are_streamed_into_output() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    fs::create_dir_all(&input_dir).unwrap();
+    let mut test_image = create_test_png_data();
+    test_image.extend((0..1_000_000).map(|i| (i % 251) as u8));
+
+    let page = json!({ "id": "page", "screenshot": "https://example.com/page.png" });
+    fs::write(input_dir.join("page.json"), page.to_string()).unwrap();
+    let lines = [
+        json!({ "screenshot": "https://example.com/1.png" }).to_string(),
+        "not json".to_string(),
+        json!({ "screenshot": "https://example.com/2.png" }).to_string(),
+    ];
+    fs::write(input_dir.join("pages.jsonl"), lines.join("\n")).unwrap();
+
+    // A closure sink only implements `write`, so streamed outputs arrive in one piece
+    let outputs = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let collected = Arc::clone(&outputs);
+    let processor = Processor::builder(
+        temp_dir.path().join("images"),
+        temp_dir.path().join("base64"),
+    )
+    .fetcher(Arc::new(FixtureFetcher {
+        bytes: test_image.clone(),
+        requested: Default::default(),
+    }))
+    .output_sink(Arc::new(
+        move |kind: OutputKind, path: &Path, bytes: &[u8]| {
+            if kind == OutputKind::Json {
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                collected.lock().unwrap().push((name, bytes.to_vec()));
+            }
+            Ok(())
+        },
+    ))
+    .build()
+    .unwrap();
+    let results = processor.process_dir(&input_dir).unwrap();
+    assert_eq!(results.len(), 2);
+
+    let data_url = format!(
+        "data:image/png;base64,{}",
+        general_purpose::STANDARD.encode(&test_image)
+    );
+    let mut outputs = outputs.lock().unwrap().clone();
+    outputs.sort();
+    let (name, json_output) = &outputs[0];
+    assert_eq!(name, "page.json");
+    let expected = json!({ "id": "page", "screenshot": data_url });
+    assert_eq!(
+        String::from_utf8(json_output.clone()).unwrap(),
+        serde_json::to_string_pretty(&expected).unwrap()
+    );
+
+    let (name, jsonl_output) = &outputs[1];
+    assert_eq!(name, "pages.jsonl");
+    let jsonl_output = String::from_utf8(jsonl_output.clone()).unwrap();
+    let output_lines: Vec<&str> = jsonl_output.lines().collect();
+    assert_eq!(
+        output_lines,
+        [
+            json!({ "screenshot": data_url }).to_string().as_str(),
+            "not json",
+            json!({ "screenshot": data_url }).to_string().as_str(),
+        ]
+    );
+
+    // Documents handed back in memory hold real data URLs too
+    let mut document = page.clone();
+    processor.process_value(&mut document, Path::new("page.json"));
+    assert_eq!(document["screenshot"], data_url);
+}
+
+/// Counts the data URLs written into JSON outputs so far, and how many fetched images were
+/// waiting to be written at most.
+#[derive(Default)]
+struct ImageCounter {
+    fetched: AtomicUsize,
+    written: AtomicUsize,
+    max_held: AtomicUsize,
+}
+
+impl ScreenshotFetcher for ImageCounter {
+    fn fetch<'a>(&'a self, _url: &'a str) -> FetchFuture<'a> {
+        let fetched = self.fetched.fetch_add(1, Ordering::SeqCst) + 1;
+        let held = fetched - self.written.load(Ordering::SeqCst);
+        self.max_held.fetch_max(held, Ordering::SeqCst);
+        Box::pin(async move {
+            Ok(FetchedImage {
+                bytes: create_test_png_data(),
+                content_type: None,
+            })
+        })
+    }
+}
+
+impl OutputSink for ImageCounter {
+    fn write(&self, _kind: OutputKind, _path: &Path, _bytes: &[u8]) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    fn write_stream(
+        &self,
+        _kind: OutputKind,
+        path: &Path,
+        write_output: &mut WriteOutput,
+    ) -> Result<(), AppError> {
+        // The prefix of each data URL arrives in a write of its own
+        struct DataUrls<'a>(&'a AtomicUsize);
+        impl std::io::Write for DataUrls<'_> {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                if bytes.starts_with(b"data:image/") {
+                    self.0.fetch_add(1, Ordering::SeqCst);
+                }
+                Ok(bytes.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        write_output(&mut DataUrls(&self.written)).map_err(|source| AppError::Write {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+#[test]
+fn test_documents_are_written_as_they_finish() {
+    let temp_dir = tempdir().unwrap();
+    let pages: Vec<Value> = (0..50)
+        .map(|index| json!({ "screenshot": format!("https://example.com/{}.png", index) }))
+        .collect();
+    let jsonl_path = temp_dir.path().join("pages.jsonl");
+    let lines: Vec<String> = pages.iter().map(Value::to_string).collect();
+    fs::write(&jsonl_path, lines.join("\n")).unwrap();
+    let crawl_path = temp_dir.path().join("crawl.json");
+    fs::write(
+        &crawl_path,
+        json!({ "success": true, "data": pages }).to_string(),
+    )
+    .unwrap();
+
+    for (json_path, explode_envelopes) in [
+        (&jsonl_path, false),
+        (&crawl_path, false),
+        (&crawl_path, true),
+    ] {
+        let counter = Arc::new(ImageCounter::default());
+        let processor = Processor::builder(
+            temp_dir.path().join("images"),
+            temp_dir.path().join("base64"),
+        )
+        .fetcher(counter.clone())
+        .output_sink(counter.clone())
+        .explode_envelopes(explode_envelopes)
+        .concurrency(2)
+        .build()
+        .unwrap();
+        let result = processor.process_file(json_path);
+        assert!(
+            matches!(&result, FileProcessResult::Success(reports) if reports.len() == 50),
+            "{:?}",
+            result
+        );
+        assert_eq!(counter.written.load(Ordering::SeqCst), 50);
+
+        // Besides the documents being rewritten, only a few finished ones wait to be written
+        let max_held = counter.max_held.load(Ordering::SeqCst);
+        assert!(
+            max_held <= 5,
+            "{} images of {:?} held at once",
+            max_held,
+            json_path
+        );
+    }
+}
+
+/// Writes to the filesystem, but fails every streamed output after its first `budget` bytes.
+struct FailingFsSink {
+    budget: usize,
+}
+
+impl OutputSink for FailingFsSink {
+    fn write(&self, kind: OutputKind, path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+        FsSink.write(kind, path, bytes)
+    }
+
+    fn write_stream(
+        &self,
+        kind: OutputKind,
+        path: &Path,
+        write_output: &mut WriteOutput,
+    ) -> Result<(), AppError> {
+        struct Failing<'a> {
+            inner: &'a mut dyn std::io::Write,
+            budget: usize,
+        }
+        impl std::io::Write for Failing<'_> {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                if bytes.len() > self.budget {
+                    return Err(std::io::Error::other("disk full"));
+                }
+                self.budget -= bytes.len();
+                self.inner.write(bytes)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.inner.flush()
+            }
+        }
+        let budget = self.budget;
+        FsSink.write_stream(kind, path, &mut |writer: &mut dyn std::io::Write| {
+            write_output(&mut Failing {
+                inner: writer,
+                budget,
+            })
+        })
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        FsSink.modified(path)
+    }
+}
+
+#[test]
+fn test_failed_output_write_leaves_no_partial_file() {
+    let temp_dir = tempdir().unwrap();
+    let base64_dir = temp_dir.path().join("base64");
+    let jsonl_path = temp_dir.path().join("pages.jsonl");
+    let lines: Vec<String> = (0..3)
+        .map(|index| json!({ "screenshot": format!("https://example.com/{}.png", index) }))
+        .map(|line| line.to_string())
+        .collect();
+    fs::write(&jsonl_path, lines.join("\n")).unwrap();
+    let processor = |sink: Arc<dyn OutputSink>| {
+        Processor::builder(temp_dir.path().join("images"), &base64_dir)
+            .fetcher(Arc::new(FixtureFetcher {
+                bytes: create_test_png_data(),
+                requested: Default::default(),
+            }))
+            .output_sink(sink)
+            .incremental(true)
+            .build()
+            .unwrap()
+    };
+
+    // The first line is written before the output fails
+    let result = processor(Arc::new(FailingFsSink { budget: 100 })).process_file(&jsonl_path);
+    assert!(
+        matches!(result, FileProcessResult::Failed(_, AppError::Write { .. })),
+        "{:?}",
+        result
+    );
+    assert!(fs::read_dir(&base64_dir).unwrap().next().is_none());
+
+    // So the next incremental run does not take the file for up to date
+    let result = processor(Arc::new(FsSink)).process_file(&jsonl_path);
+    assert!(
+        matches!(result, FileProcessResult::Success(_)),
+        "{:?}",
+        result
+    );
+    let output = fs::read_to_string(base64_dir.join("pages.jsonl")).unwrap();
+    assert_eq!(output.lines().count(), 3);
+}
+
+#[test]
+fn test_screenshots_can_be_linked_instead_of_inlined() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("out/images");
+    let base64_dir = temp_dir.path().join("out/base64");
+    fs::create_dir_all(input_dir.join("shard")).unwrap();
+    let json_path = create_test_json_file(
+        &input_dir.join("shard"),
+        "page.json",
+        "https://example.com/shots/home.png",
+    )
+    .unwrap();
+    let test_image = create_test_png_data();
+    let fetcher = Arc::new(FixtureFetcher {
+        bytes: test_image.clone(),
+        requested: Default::default(),
+    });
+
+    // Paths are relative to the output JSON, which mirrors the input layout
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .fetcher(fetcher.clone())
+        .target(ScreenshotTarget::RelativePath)
+        .build()
+        .unwrap();
+    let results = processor.process_dir(&input_dir).unwrap();
+    match &results[0].1 {
+        FileProcessResult::Success(reports) => assert!(matches!(
+            &reports[0].status,
+            FieldStatus::Linked { image_path } if image_path.ends_with("shard/home.png")
+        )),
+        other => panic!("Expected success but got: {:?}", other),
+    }
+    let output: Value =
+        serde_json::from_str(&fs::read_to_string(base64_dir.join("shard/page.json")).unwrap())
+            .unwrap();
+    assert_eq!(output["screenshot"], "../../images/shard/home.png");
+    assert_eq!(
+        fs::read(image_dir.join("shard/home.png")).unwrap(),
+        test_image
+    );
+
+    // Templates can address the image by its content
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .fetcher(fetcher)
+        .target(ScreenshotTarget::UrlTemplate(
+            "https://cdn.example/{sha256}.{ext}?name={filename}".to_string(),
+        ))
+        .build()
+        .unwrap();
+    let mut document: Value =
+        serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+    processor.process_value(&mut document, &json_path);
+    let sha256 = "2aa4fa20701cdd6d8d56046069001186b5267e3ee7d0ef618ad2f4a683723e11";
+    let expected = format!("https://cdn.example/{}.png?name=home.png", sha256);
+    assert_eq!(document["screenshot"], expected);
+}
+
+#[test]
+fn test_content_addressed_images_are_deduplicated() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    let image_dir = temp_dir.path().join("images");
+    let base64_dir = temp_dir.path().join("base64");
+    fs::create_dir_all(input_dir.join("a")).unwrap();
+    fs::create_dir_all(input_dir.join("b")).unwrap();
+    // The same login wall, served under different URLs
+    create_test_json_file(
+        &input_dir.join("a"),
+        "page.json",
+        "https://example.com/1.png",
+    )
+    .unwrap();
+    create_test_json_file(&input_dir.join("b"), "page.json", "https://example.com/2").unwrap();
+    let test_image = create_test_png_data();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let processor = Processor::builder(&image_dir, &base64_dir)
+        .fetcher(Arc::new(FixtureFetcher {
+            bytes: test_image.clone(),
+            requested: Default::default(),
+        }))
+        .content_addressed(true)
+        .on_event(Arc::new(move |event: &ProgressEvent| {
+            sender.send(event.clone()).unwrap();
+        }))
+        .build()
+        .unwrap();
+    let results = processor.process_dir(&input_dir).unwrap();
+    assert_eq!(results.len(), 2);
+
+    let sha256 = "2aa4fa20701cdd6d8d56046069001186b5267e3ee7d0ef618ad2f4a683723e11";
+    let stored_path = image_dir.join("2a").join(format!("{}.png", sha256));
+    assert_eq!(fs::read(&stored_path).unwrap(), test_image);
+    let stored: Vec<_> = walkdir::WalkDir::new(&image_dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .collect();
+    assert_eq!(stored.len(), 1);
+    for (_, result) in &results {
+        match result {
+            FileProcessResult::Success(reports) => assert!(matches!(
+                &reports[0].status,
+                FieldStatus::Inlined { image_path } if *image_path == stored_path
+            )),
+            other => panic!("Expected success but got: {:?}", other),
+        }
+    }
+    assert_eq!(
+        processor.deduplication(),
+        Deduplication {
+            images: 1,
+            bytes: test_image.len() as u64,
+        }
+    );
+    drop(processor);
+    let deduplicated: Vec<ProgressEvent> = receiver
+        .iter()
+        .filter(|event| matches!(event, ProgressEvent::Deduplicated { .. }))
+        .collect();
+    assert_eq!(deduplicated.len(), 1);
+}
+
+#[test]
+fn test_failed_content_addressed_write_is_retried_by_waiting_fields() {
+    let temp_dir = tempdir().unwrap();
+    let image_dir = temp_dir.path().join("images");
+    let test_image = create_test_png_data();
+    // Three pages, rewritten concurrently, holding the same image; the first write of it
+    // fails after a while, when the other pages are already waiting for it
+    let mut document = json!({
+        "success": true,
+        "data": [
+            { "screenshot": "https://example.com/1.png" },
+            { "screenshot": "https://example.com/2.png" },
+            { "screenshot": "https://example.com/3.png" }
+        ]
+    });
+
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let stored = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let sink = {
+        let attempts = Arc::clone(&attempts);
+        let stored = Arc::clone(&stored);
+        move |_kind: OutputKind, path: &Path, _bytes: &[u8]| {
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                std::thread::sleep(Duration::from_millis(50));
+                return Err(AppError::Write {
+                    path: path.to_path_buf(),
+                    source: std::io::Error::other("disk full"),
+                });
+            }
+            stored.lock().unwrap().push(path.to_path_buf());
+            Ok(())
+        }
+    };
+    let processor = Processor::builder(&image_dir, temp_dir.path().join("base64"))
+        .fetcher(Arc::new(FixtureFetcher {
+            bytes: test_image.clone(),
+            requested: Default::default(),
+        }))
+        .output_sink(Arc::new(sink))
+        .content_addressed(true)
+        .build()
+        .unwrap();
+    let reports = processor.process_value(&mut document, Path::new("page.json"));
+    assert_eq!(reports.len(), 3);
+
+    // The image is written once more after the failure, and every inlined field refers to it
+    let stored = stored.lock().unwrap().clone();
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(stored.len(), 1);
+    let failed = reports
+        .iter()
+        .filter(|report| matches!(report.status, FieldStatus::Failed(AppError::Write { .. })))
+        .count();
+    assert_eq!(failed, 1, "{:?}", reports);
+    for report in &reports {
+        if let FieldStatus::Inlined { image_path } = &report.status {
+            assert_eq!(*image_path, stored[0]);
+        }
+    }
+    assert_eq!(processor.deduplication().images, 1);
+}
+
+#[test]
+fn test_image_name_collisions_are_resolved() {
+    let temp_dir = tempdir().unwrap();
+    let input_dir = temp_dir.path().join("input");
+    fs::create_dir_all(&input_dir).unwrap();
+    // Two pages whose screenshot URLs share their last path segment
+    create_test_json_file(
+        &input_dir,
+        "a.json",
+        "https://a.example/shots/screenshot.png",
+    )
+    .unwrap();
+    create_test_json_file(
+        &input_dir,
+        "b.json",
+        "https://b.example/shots/screenshot.png",
+    )
+    .unwrap();
+    let fixture = || {
+        Arc::new(FixtureFetcher {
+            bytes: create_test_png_data(),
+            requested: Default::default(),
+        })
+    };
+
+    let image_dir = temp_dir.path().join("suffixed");
+    let processor = Processor::builder(&image_dir, temp_dir.path().join("base64"))
+        .fetcher(fixture())
+        .build()
+        .unwrap();
+    let results = processor.process_dir(&input_dir).unwrap();
+    let mut saved: Vec<PathBuf> = results
+        .iter()
+        .map(|(_, result)| match result {
+            FileProcessResult::Success(reports) => match &reports[0].status {
+                FieldStatus::Inlined { image_path } => image_path.clone(),
+                other => panic!("Expected an inlined field but got: {:?}", other),
+            },
+            other => panic!("Expected success but got: {:?}", other),
+        })
+        .collect();
+    saved.sort();
+    assert_eq!(
+        saved,
+        vec![
+            image_dir.join("screenshot-2.png"),
+            image_dir.join("screenshot.png")
+        ]
+    );
+
+    let processor = Processor::builder(
+        temp_dir.path().join("strict"),
+        temp_dir.path().join("base64"),
+    )
+    .fetcher(fixture())
+    .on_collision(OnCollision::Error)
+    .build()
+    .unwrap();
+    let results = processor.process_dir(&input_dir).unwrap();
+    let collisions = results
+        .iter()
+        .filter(|(_, result)| {
+            // The file's only field failed, so the whole file did
+            matches!(
+                result,
+                FileProcessResult::Failed(_, AppError::NameCollision { .. })
+            )
+        })
+        .count();
+    assert_eq!(collisions, 1);
+}
+
+#[test]
+fn test_image_naming_strategies() {
+    let temp_dir = tempdir().unwrap();
+    let json_path = create_test_json_file(
+        temp_dir.path(),
+        "page.json",
+        "https://example.com/a/CON%3Ashot%20%2A1.png?token=abc",
+    )
+    .unwrap();
+    let name_with = |naming: ImageNaming| {
+        let image_dir = temp_dir.path().join("images");
+        let processor = Processor::builder(&image_dir, temp_dir.path().join("base64"))
+            .fetcher(Arc::new(FixtureFetcher {
+                bytes: create_test_png_data(),
+                requested: Default::default(),
+            }))
+            .naming(naming)
+            .build()
+            .unwrap();
+        match processor.process_file(&json_path) {
+            FileProcessResult::Success(reports) => match &reports[0].status {
+                FieldStatus::Inlined { image_path } => image_path
+                    .strip_prefix(&image_dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned(),
+                other => panic!("Expected an inlined field but got: {:?}", other),
+            },
+            other => panic!("Expected success but got: {:?}", other),
+        }
+    };
+
+    // The URL name is percent-decoded and made safe for Windows
+    assert_eq!(name_with(ImageNaming::Url), "CON_shot _1.png");
+    assert_eq!(name_with(ImageNaming::JsonStem), "page.png");
+    let sha256 = "2aa4fa20701cdd6d8d56046069001186b5267e3ee7d0ef618ad2f4a683723e11";
+    assert_eq!(name_with(ImageNaming::Hash), format!("{}.png", sha256));
+    assert_eq!(
+        name_with("{stem}-{hash8}.{ext}".parse().unwrap()),
+        "page-2aa4fa20.png"
+    );
+    assert!("jpeg".parse::<ImageNaming>().is_err());
+
+    assert_eq!(sanitize_filename("../../etc/passwd"), "_.._etc_passwd");
+    assert_eq!(sanitize_filename("nul.txt"), "_nul.txt");
+    assert_eq!(sanitize_filename(" . "), "image");
+    let long_name = sanitize_filename(&format!("{}.png", "é".repeat(300)));
+    assert!(long_name.len() <= 200 && long_name.ends_with("é.png"));
+}
+
+/// Serves fixed bytes with a fixed `Content-Type` for every URL.
+struct TypedFetcher {
+    bytes: Vec<u8>,
+    content_type: Option<&'static str>,
+}
+
+impl ScreenshotFetcher for TypedFetcher {
+    fn fetch<'a>(&'a self, _url: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move {
+            Ok(FetchedImage {
+                bytes: self.bytes.clone(),
+                content_type: self.content_type.map(String::from),
+            })
+        })
+    }
+}
+
+#[test]
+fn test_image_extension_follows_detected_type() {
+    let temp_dir = tempdir().unwrap();
+    let image_dir = temp_dir.path().join("images");
+    // A JPEG served from a '.png' URL with a 'Content-Type' claiming PNG
+    let jpeg = create_test_jpg_data();
+    let inline_with = |url: &str, bytes: &[u8], on_type_mismatch: OnTypeMismatch| {
+        let processor = Processor::builder(&image_dir, temp_dir.path().join("base64"))
+            .fetcher(Arc::new(TypedFetcher {
+                bytes: bytes.to_vec(),
+                content_type: Some("image/png; charset=binary"),
+            }))
+            .on_type_mismatch(on_type_mismatch)
+            .build()
+            .unwrap();
+        let mut document = json!({ "screenshot": url });
+        let reports = processor.process_value(&mut document, Path::new("page.json"));
+        let status = reports.into_iter().next().unwrap().status;
+        (status, document["screenshot"].as_str().unwrap().to_string())
+    };
+
+    let (status, data_url) = inline_with(
+        "https://example.com/shot.png",
+        &jpeg,
+        OnTypeMismatch::TrustSniffed,
+    );
+    assert!(matches!(
+        status,
+        FieldStatus::Inlined { image_path } if image_path == image_dir.join("shot.jpg")
+    ));
+    assert!(data_url.starts_with("data:image/jpeg;base64,"));
+
+    let (status, data_url) = inline_with(
+        "https://example.com/shot.png",
+        &jpeg,
+        OnTypeMismatch::TrustHeader,
+    );
+    assert!(matches!(
+        status,
+        FieldStatus::Inlined { image_path } if image_path == image_dir.join("shot.png")
+    ));
+    assert!(data_url.starts_with("data:image/png;base64,"));
+
+    let (status, url) = inline_with("https://example.com/shot.png", &jpeg, OnTypeMismatch::Fail);
+    assert!(matches!(
+        status,
+        FieldStatus::Failed(AppError::TypeMismatch { .. })
+    ));
+    assert_eq!(url, "https://example.com/shot.png");
+
+    // Agreeing types pass the strict policy, and extensionless URLs get one
+    let (status, _) = inline_with(
+        "https://example.com/capture",
+        &create_test_png_data(),
+        OnTypeMismatch::Fail,
+    );
+    assert!(matches!(
+        status,
+        FieldStatus::Inlined { image_path } if image_path == image_dir.join("capture.png")
+    ));
+}
+
+#[test]
+fn test_non_image_payloads_are_rejected() {
+    let temp_dir = tempdir().unwrap();
+    let image_dir = temp_dir.path().join("images");
+    let expired_url = "https://storage.example/shots/expired.png";
+    // What a storage bucket answers with '200 OK' once a signed URL has expired
+    let error_page =
+        b"<?xml version='1.0' encoding='UTF-8'?><Error><Code>ExpiredToken</Code></Error>";
+    let truncated_png = create_test_png_data()[..33].to_vec();
+    let inline_with = |bytes: &[u8], validation: Option<ImageValidation>| {
+        let processor = Processor::builder(&image_dir, temp_dir.path().join("base64"))
+            .fetcher(Arc::new(FixtureFetcher {
+                bytes: bytes.to_vec(),
+                requested: Default::default(),
+            }))
+            .validation(validation)
+            .build()
+            .unwrap();
+        let mut document = json!({ "screenshot": expired_url });
+        let reports = processor.process_value(&mut document, Path::new("page.json"));
+        (reports.into_iter().next().unwrap().status, document)
+    };
+
+    let only_webp = ImageValidation {
+        allowed_types: vec!["webp".to_string()],
+        ..Default::default()
+    };
+    for (bytes, validation, reason) in [
+        (
+            &error_page[..],
+            Some(ImageValidation::default()),
+            "text/xml",
+        ),
+        (
+            &truncated_png[..],
+            Some(ImageValidation::default()),
+            "does not decode",
+        ),
+        (
+            &create_test_png_data()[..],
+            Some(only_webp),
+            "not an allowed type",
+        ),
+    ] {
+        let (status, document) = inline_with(bytes, validation);
+        match status {
+            FieldStatus::Rejected(AppError::InvalidImage {
+                url,
+                reason: actual,
+            }) => {
+                assert_eq!(url, expired_url);
+                assert!(actual.contains(reason), "unexpected reason: {}", actual);
+            }
+            other => panic!("Expected a rejected field but got: {:?}", other),
+        }
+        assert_eq!(document["screenshot"], expired_url);
+        assert!(!image_dir.join("expired.png").exists());
+    }
+
+    // A file whose only screenshot is rejected is not written, and says so
+    let input_dir = temp_dir.path().join("input");
+    fs::create_dir_all(&input_dir).unwrap();
+    let json_path = create_test_json_file(&input_dir, "page.json", expired_url).unwrap();
+    let processor = Processor::builder(&image_dir, temp_dir.path().join("base64"))
+        .fetcher(Arc::new(FixtureFetcher {
+            bytes: error_page.to_vec(),
+            requested: Default::default(),
+        }))
+        .build()
+        .unwrap();
+    match processor.process_file(&json_path) {
+        FileProcessResult::Rejected(file_name, reports) => {
+            assert_eq!(file_name, "page.json");
+            assert_eq!(reports.len(), 1);
+            assert!(matches!(reports[0].status, FieldStatus::Rejected(_)));
+        }
+        other => panic!("Expected a rejected file but got: {:?}", other),
+    }
+    assert!(!temp_dir.path().join("base64/page.json").exists());
+
+    // Without validation, anything is inlined
+    let (status, document) = inline_with(error_page, None);
+    assert!(matches!(status, FieldStatus::Inlined { .. }));
+    assert!(
+        document["screenshot"]
+            .as_str()
+            .unwrap()
+            .starts_with("data:text/xml;base64,")
+    );
+}
+
+/// A PNG of pseudo-random pixels, which lossy formats compress far better than PNG does.
+fn create_noisy_png_data(size: u32) -> Vec<u8> {
+    let mut state = 0x2545_F491_u32;
+    let image = image::RgbImage::from_fn(size, size, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let [r, g, b, _] = state.to_le_bytes();
+        image::Rgb([r, g, b])
+    });
+    let mut bytes = Vec::new();
+    image
+        .write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    bytes
+}
+
+#[test]
+fn test_images_are_transcoded_before_inlining() {
+    let temp_dir = tempdir().unwrap();
+    let noisy_png = create_noisy_png_data(64);
+    let inline_with = |name: &str, bytes: &[u8], transcode: Transcode| {
+        let image_dir = temp_dir.path().join(name);
+        let processor = Processor::builder(&image_dir, temp_dir.path().join("base64"))
+            .fetcher(Arc::new(FixtureFetcher {
+                bytes: bytes.to_vec(),
+                requested: Default::default(),
+            }))
+            .transcode(Some(transcode))
+            .build()
+            .unwrap();
+        let mut document = json!({ "screenshot": "https://example.com/shot.png" });
+        let reports = processor.process_value(&mut document, Path::new("page.json"));
+        let image_path = match reports.into_iter().next().unwrap().status {
+            FieldStatus::Inlined { image_path } => image_path,
+            other => panic!("Expected an inlined field but got: {:?}", other),
+        };
+        let data_url = document["screenshot"].as_str().unwrap().to_string();
+        let (header, payload) = data_url.split_once(',').unwrap();
+        let inlined = general_purpose::STANDARD.decode(payload).unwrap();
+        (image_path, header.to_string(), inlined)
+    };
+
+    // The original stays in the image directory; only the data URL is transcoded
+    let (image_path, header, inlined) =
+        inline_with("webp", &noisy_png, Transcode::new(TranscodeFormat::WebP));
+    assert_eq!(image_path.file_name().unwrap(), "shot.png");
+    assert_eq!(fs::read(&image_path).unwrap(), noisy_png);
+    assert_eq!(header, "data:image/webp;base64");
+    assert_eq!(infer::get(&inlined).unwrap().mime_type(), "image/webp");
+    assert!(inlined.len() < noisy_png.len());
+
+    let (image_path, header, inlined) = inline_with(
+        "jpeg",
+        &noisy_png,
+        Transcode {
+            quality: 50,
+            keep_original: false,
+            ..Transcode::new(TranscodeFormat::Jpeg)
+        },
+    );
+    assert_eq!(image_path.file_name().unwrap(), "shot.jpg");
+    assert_eq!(fs::read(&image_path).unwrap(), inlined);
+    assert_eq!(header, "data:image/jpeg;base64");
+
+    let (_, header, inlined) = inline_with(
+        "avif",
+        &create_noisy_png_data(16),
+        Transcode::new(TranscodeFormat::Avif),
+    );
+    assert_eq!(header, "data:image/avif;base64");
+    assert_eq!(infer::get(&inlined).unwrap().mime_type(), "image/avif");
+
+    // A single pixel only grows when re-encoded, so it is inlined as downloaded
+    let (_, header, inlined) = inline_with(
+        "tiny",
+        &create_test_png_data(),
+        Transcode::new(TranscodeFormat::WebP),
+    );
+    assert_eq!(header, "data:image/png;base64");
+    assert_eq!(inlined, create_test_png_data());
+}
+
+#[test]
+fn test_images_are_resized_and_thumbnailed() {
+    let temp_dir = tempdir().unwrap();
+    let noisy_png = create_noisy_png_data(64);
+    let image_dir = temp_dir.path().join("images");
+    let processor = Processor::builder(&image_dir, temp_dir.path().join("base64"))
+        .fetcher(Arc::new(FixtureFetcher {
+            bytes: noisy_png.clone(),
+            requested: Default::default(),
+        }))
+        .resize(Some(Resize {
+            max_width: Some(16),
+            max_height: None,
+        }))
+        .thumbnail(Some(Thumbnail::new("8x4".parse().unwrap())))
+        .build()
+        .unwrap();
+    let mut document = json!({
+        "screenshot": "https://example.com/shot.png",
+        "actions": { "screenshots": ["https://example.com/action.png"] }
+    });
+    let reports = processor.process_value(&mut document, Path::new("page.json"));
+    assert!(
+        reports
+            .iter()
+            .all(|report| matches!(report.status, FieldStatus::Inlined { .. }))
+    );
+    let decode = |field: &Value| {
+        let (header, payload) = field.as_str().unwrap().split_once(',').unwrap();
+        let bytes = general_purpose::STANDARD.decode(payload).unwrap();
+        (header.to_string(), image::load_from_memory(&bytes).unwrap())
+    };
+
+    // The original is saved; the inlined copy keeps its format but is scaled down
+    assert_eq!(fs::read(image_dir.join("shot.png")).unwrap(), noisy_png);
+    let (header, inlined) = decode(&document["screenshot"]);
+    assert_eq!(header, "data:image/png;base64");
+    assert_eq!((inlined.width(), inlined.height()), (16, 16));
+
+    // Thumbnails have their own size and format, and are only added next to single URLs
+    let (header, thumbnail) = decode(&document["screenshotThumbnail"]);
+    assert_eq!(header, "data:image/webp;base64");
+    assert_eq!((thumbnail.width(), thumbnail.height()), (4, 4));
+    assert_eq!(
+        document["actions"].as_object().unwrap().len(),
+        1,
+        "array entries get no thumbnail"
+    );
+
+    // Bounds never scale images up
+    let resize: Resize = "x300".parse().unwrap();
+    assert_eq!(resize.fit(1280, 6000), Some((64, 300)));
+    assert_eq!(resize.fit(400, 200), None);
+    assert!("0x300".parse::<Resize>().is_err());
+    assert!("x".parse::<Resize>().is_err());
+}
+
+#[test]
+fn test_tall_screenshots_are_cropped_or_tiled() {
+    let temp_dir = tempdir().unwrap();
+    let mut tall_png = Vec::new();
+    image::RgbImage::from_fn(8, 25, |_, y| image::Rgb([y as u8 * 10, 0, 0]))
+        .write_to(
+            &mut std::io::Cursor::new(&mut tall_png),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    let inline_with = |name: &str, split: Split| {
+        let image_dir = temp_dir.path().join(name);
+        let processor = Processor::builder(&image_dir, temp_dir.path().join("base64"))
+            .fetcher(Arc::new(FixtureFetcher {
+                bytes: tall_png.clone(),
+                requested: Default::default(),
+            }))
+            .split(Some(split))
+            .build()
+            .unwrap();
+        let mut document = json!({ "screenshot": "https://example.com/shot.png" });
+        let reports = processor.process_value(&mut document, Path::new("page.json"));
+        assert!(matches!(reports[0].status, FieldStatus::Inlined { .. }));
+        (image_dir, document)
+    };
+    let decode = |field: &Value| {
+        let (header, payload) = field.as_str().unwrap().split_once(',').unwrap();
+        assert_eq!(header, "data:image/png;base64");
+        let bytes = general_purpose::STANDARD.decode(payload).unwrap();
+        image::load_from_memory(&bytes).unwrap()
+    };
+
+    // Cropping only affects the inlined copy
+    let (image_dir, document) = inline_with("crop", Split::Crop { height: 10 });
+    assert_eq!(fs::read(image_dir.join("shot.png")).unwrap(), tall_png);
+    let cropped = decode(&document["screenshot"]);
+    assert_eq!((cropped.width(), cropped.height()), (8, 10));
+
+    // Tiles go to the configured field, top to bottom, and are saved next to the screenshot
+    let (image_dir, document) = inline_with(
+        "tiles",
+        Split::Tiles {
+            height: 10,
+            field: "tiles".to_string(),
+        },
+    );
+    assert_eq!(decode(&document["screenshot"]).height(), 25);
+    let tiles = document["tiles"].as_array().unwrap();
+    assert_eq!(tiles.len(), 3);
+    for (index, (tile, height)) in tiles.iter().zip([10, 10, 5]).enumerate() {
+        let tile = decode(tile);
+        assert_eq!((tile.width(), tile.height()), (8, height));
+        assert_eq!(tile.to_rgb8().get_pixel(0, 0)[0], index as u8 * 100);
+        let saved = image_dir.join(format!("shot-tile{}.png", index + 1));
+        assert_eq!(image::open(saved).unwrap().to_rgb8(), tile.to_rgb8());
+    }
+
+    // Content-addressed tiles are named after their own bytes, so a rerun with another tile
+    // height stores and references new tiles instead of the ones already there
+    let input_dir = temp_dir.path().join("input");
+    fs::create_dir_all(&input_dir).unwrap();
+    let json_path =
+        create_test_json_file(&input_dir, "page.json", "https://example.com/shot.png").unwrap();
+    let image_dir = temp_dir.path().join("stored");
+    let base64_dir = temp_dir.path().join("linked");
+    for height in [20, 10] {
+        let processor = Processor::builder(&image_dir, &base64_dir)
+            .fetcher(Arc::new(FixtureFetcher {
+                bytes: tall_png.clone(),
+                requested: Default::default(),
+            }))
+            .content_addressed(true)
+            .target(ScreenshotTarget::RelativePath)
+            .split(Some(Split::tiles(height)))
+            .build()
+            .unwrap();
+        let result = processor.process_file(&json_path);
+        assert!(
+            matches!(result, FileProcessResult::Success(_)),
+            "{:?}",
+            result
+        );
+        let output: Value =
+            serde_json::from_str(&fs::read_to_string(base64_dir.join("page.json")).unwrap())
+                .unwrap();
+        let tiles = output["screenshotTiles"].as_array().unwrap();
+        assert_eq!(tiles.len(), 25usize.div_ceil(height as usize));
+        for (index, tile) in tiles.iter().enumerate() {
+            let tile = image::open(base64_dir.join(tile.as_str().unwrap())).unwrap();
+            let expected = (25 - index as u32 * height).min(height);
+            assert_eq!((tile.width(), tile.height()), (8, expected));
+        }
+    }
+
+    let invalid = Processor::builder(temp_dir.path(), temp_dir.path())
+        .split(Some(Split::Tiles {
+            height: 10,
+            field: String::new(),
+        }))
+        .build();
+    assert!(matches!(invalid, Err(AppError::InvalidInput(_))));
+}